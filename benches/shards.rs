@@ -0,0 +1,134 @@
+//! Messages per second through `ChatServer` with a growing number of room
+//! shards. Every room has the same members, every message is fanned out to
+//! all of them but the sender.
+//!
+//!     cargo bench --bench shards
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use actix::prelude::*;
+use verdant_chat::{broker::LocalBroker, server};
+
+const ROOMS: usize = 64;
+const MEMBERS: usize = 50;
+const MESSAGES: usize = 20_000;
+const SHARDS: &[usize] = &[1, 2, 4, 8];
+/// Threads the fake sessions run on, the same for every shard count
+const SESSION_THREADS: usize = 8;
+const PREFIX: &str = "bench ";
+
+/// Stands in for a websocket session, counts the benchmark messages
+struct Member {
+    received: Arc<AtomicUsize>,
+}
+
+impl Actor for Member {
+    type Context = Context<Self>;
+}
+
+impl Handler<server::Message> for Member {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Message, _: &mut Context<Self>) {
+        // join and leave notices don't count
+        if msg.0.starts_with(PREFIX) {
+            self.received.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Handler<server::Kicked> for Member {
+    type Result = ();
+
+    fn handle(&mut self, _: server::Kicked, _: &mut Context<Self>) {}
+}
+
+impl Handler<server::Restarting> for Member {
+    type Result = ();
+
+    fn handle(&mut self, _: server::Restarting, _: &mut Context<Self>) {}
+}
+
+/// Seconds it takes to deliver `MESSAGES` with `shards` room shards
+async fn run(shards: usize, threads: &[Arbiter]) -> f64 {
+    let received = Arc::new(AtomicUsize::new(0));
+    let chat = server::ChatServer::new(
+        Arc::new(AtomicUsize::new(0)),
+        "main".to_owned(),
+        Arc::new(LocalBroker::default()),
+        shards,
+    )
+    .start();
+
+    // senders post straight to the shard of their room like sessions do
+    let mut room_shards = Vec::with_capacity(ROOMS);
+    for room in 0..ROOMS {
+        for member in 0..MEMBERS {
+            let id = format!("{room}-{member}");
+            let received = received.clone();
+            let addr = Member::start_in_arbiter(&threads[member % threads.len()].handle(), move |_| Member { received });
+            chat.send(server::Connect {
+                addr: addr.clone().recipient(),
+                kicked: addr.clone().recipient(),
+                restarting: addr.recipient(),
+                id: id.clone(),
+            })
+            .await
+            .unwrap();
+            let shard = chat
+                .send(server::Join {
+                    id,
+                    name: format!("room-{room}"),
+                    hidden: false,
+                })
+                .await
+                .unwrap();
+            if member == 0 {
+                room_shards.push(shard);
+            }
+        }
+    }
+
+    let expected = MESSAGES * (MEMBERS - 1);
+    let start = Instant::now();
+    for n in 0..MESSAGES {
+        let room = n % ROOMS;
+        room_shards[room].do_send(server::ClientMessage {
+            id: format!("{room}-0"),
+            msg: format!("{PREFIX}{n}"),
+            room: format!("room-{room}"),
+        });
+    }
+    while received.load(Ordering::Relaxed) < expected {
+        actix::clock::sleep(Duration::from_millis(1)).await;
+    }
+    let secs = start.elapsed().as_secs_f64();
+    // the next run must not share the cores with this one's shards
+    chat.send(server::Stop).await.unwrap();
+    secs
+}
+
+fn main() {
+    System::new().block_on(async {
+        let threads: Vec<Arbiter> = (0..SESSION_THREADS).map(|_| Arbiter::new()).collect();
+        println!("{ROOMS} rooms of {MEMBERS} members, {MESSAGES} messages");
+        println!("{:>6} {:>12} {:>14}", "shards", "messages/s", "deliveries/s");
+        for &shards in SHARDS {
+            let secs = run(shards, &threads).await;
+            println!(
+                "{shards:>6} {:>12.0} {:>14.0}",
+                MESSAGES as f64 / secs,
+                (MESSAGES * (MEMBERS - 1)) as f64 / secs,
+            );
+        }
+        for thread in threads {
+            thread.stop();
+        }
+    });
+}
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE room_requests;
+DROP TABLE room_invites;
+DROP TABLE room_members;
+ALTER TABLE rooms DROP COLUMN visibility;
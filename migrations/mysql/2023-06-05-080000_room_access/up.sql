@@ -0,0 +1,31 @@
+-- Your SQL goes here
+ALTER TABLE rooms ADD COLUMN visibility INT NOT NULL DEFAULT 0;
+
+CREATE TABLE room_members (
+  room_id INT NOT NULL,
+  user_id CHAR(36) NOT NULL,
+  role INT NOT NULL,
+  PRIMARY KEY (room_id, user_id),
+  FOREIGN KEY (room_id) REFERENCES rooms(id),
+  FOREIGN KEY (user_id) REFERENCES users(uuid)
+);
+
+CREATE TABLE room_invites (
+  room_id INT NOT NULL,
+  user_id CHAR(36) NOT NULL,
+  inviter_id CHAR(36) NOT NULL,
+  time TIMESTAMP NOT NULL,
+  PRIMARY KEY (room_id, user_id),
+  FOREIGN KEY (room_id) REFERENCES rooms(id),
+  FOREIGN KEY (user_id) REFERENCES users(uuid),
+  FOREIGN KEY (inviter_id) REFERENCES users(uuid)
+);
+
+CREATE TABLE room_requests (
+  room_id INT NOT NULL,
+  user_id CHAR(36) NOT NULL,
+  time TIMESTAMP NOT NULL,
+  PRIMARY KEY (room_id, user_id),
+  FOREIGN KEY (room_id) REFERENCES rooms(id),
+  FOREIGN KEY (user_id) REFERENCES users(uuid)
+);
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TABLE room_bans (
+  room_id INT NOT NULL,
+  user_id CHAR(36) NOT NULL,
+  moderator_id CHAR(36) NOT NULL,
+  reason TEXT,
+  time TIMESTAMP NOT NULL,
+  expires TIMESTAMP NULL,
+  PRIMARY KEY (room_id, user_id),
+  FOREIGN KEY (room_id) REFERENCES rooms(id),
+  FOREIGN KEY (user_id) REFERENCES users(uuid),
+  FOREIGN KEY (moderator_id) REFERENCES users(uuid)
+);
+
+CREATE TABLE room_mutes (
+  room_id INT NOT NULL,
+  user_id CHAR(36) NOT NULL,
+  moderator_id CHAR(36) NOT NULL,
+  time TIMESTAMP NOT NULL,
+  expires TIMESTAMP NULL,
+  PRIMARY KEY (room_id, user_id),
+  FOREIGN KEY (room_id) REFERENCES rooms(id),
+  FOREIGN KEY (user_id) REFERENCES users(uuid),
+  FOREIGN KEY (moderator_id) REFERENCES users(uuid)
+);
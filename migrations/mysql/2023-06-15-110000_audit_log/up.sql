@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE audit_log (
+  id BIGINT AUTO_INCREMENT PRIMARY KEY,
+  actor_id CHAR(36) NOT NULL,
+  action VARCHAR(64) NOT NULL,
+  target VARCHAR(255),
+  room_id INT,
+  reason TEXT,
+  time TIMESTAMP NOT NULL
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+  FOR EACH ROW SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+  FOR EACH ROW SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM users WHERE uuid = '00000000-0000-0000-0000-000000000000';
+ALTER TABLE users DROP COLUMN deleted_at;
+ALTER TABLE users DROP COLUMN state;
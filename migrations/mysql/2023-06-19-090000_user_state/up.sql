@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN state INT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMP NULL;
+
+-- messages of erased users are attributed to this account
+INSERT INTO users (uuid, name, password, permission_id, state)
+  VALUES ('00000000-0000-0000-0000-000000000000', 'deleted user', '', 0, 3);
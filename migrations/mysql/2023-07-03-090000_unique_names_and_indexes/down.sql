@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX room_members_user_idx ON room_members;
+DROP INDEX messages_sender_time_idx ON messages;
+DROP INDEX messages_room_time_idx ON messages;
+ALTER TABLE rooms DROP INDEX rooms_rname_key;
+ALTER TABLE users DROP INDEX users_name_key;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- rename duplicates left over from before names were unique, the oldest
+-- account or room keeps its name
+UPDATE users u
+  JOIN (SELECT name, MIN(uuid) AS keep FROM users GROUP BY name HAVING COUNT(*) > 1) d
+    ON u.name = d.name AND u.uuid <> d.keep
+  SET u.name = CONCAT(u.name, '#', u.uuid);
+UPDATE rooms r
+  JOIN (SELECT rname, MIN(id) AS keep FROM rooms GROUP BY rname HAVING COUNT(*) > 1) d
+    ON r.rname = d.rname AND r.id <> d.keep
+  SET r.rname = CONCAT(r.rname, '#', r.id);
+
+ALTER TABLE users ADD CONSTRAINT users_name_key UNIQUE (name);
+ALTER TABLE rooms ADD CONSTRAINT rooms_rname_key UNIQUE (rname);
+
+CREATE INDEX messages_room_time_idx ON messages (room_id, time);
+CREATE INDEX messages_sender_time_idx ON messages (sender_id, time);
+CREATE INDEX room_members_user_idx ON room_members (user_id);
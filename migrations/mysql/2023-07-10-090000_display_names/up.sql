@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN display_name VARCHAR(32) NULL;
+ALTER TABLE users ADD CONSTRAINT users_display_name_key UNIQUE (display_name);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE user_profiles (
+  user_id CHAR(36) NOT NULL PRIMARY KEY,
+  avatar VARCHAR(64) NULL,
+  bio TEXT,
+  status VARCHAR(128) NULL,
+  timezone VARCHAR(64) NULL,
+  FOREIGN KEY (user_id) REFERENCES users(uuid)
+);
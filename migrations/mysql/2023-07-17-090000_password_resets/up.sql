@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN session_stamp VARCHAR(36) NOT NULL DEFAULT '';
+
+CREATE TABLE password_resets (
+  user_id CHAR(36) NOT NULL PRIMARY KEY,
+  token CHAR(64) NOT NULL,
+  expires TIMESTAMP NOT NULL,
+  CONSTRAINT password_resets_token_key UNIQUE (token),
+  FOREIGN KEY (user_id) REFERENCES users(uuid)
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE user_totp (
+  user_id CHAR(36) NOT NULL PRIMARY KEY,
+  secret VARCHAR(64) NOT NULL,
+  enabled_at TIMESTAMP NULL,
+  last_step BIGINT NOT NULL DEFAULT 0,
+  FOREIGN KEY (user_id) REFERENCES users(uuid)
+);
+
+CREATE TABLE recovery_codes (
+  user_id CHAR(36) NOT NULL,
+  code CHAR(64) NOT NULL,
+  PRIMARY KEY (user_id, code),
+  FOREIGN KEY (user_id) REFERENCES users(uuid)
+);
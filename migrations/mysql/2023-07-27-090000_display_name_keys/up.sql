@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- display names compared without case, so "Admin" can't pose as "admin"
+ALTER TABLE users ADD COLUMN display_key VARCHAR(32) NULL;
+UPDATE users SET display_key = LOWER(display_name) WHERE display_name IS NOT NULL;
+ALTER TABLE users ADD CONSTRAINT users_display_key_key UNIQUE (display_key);
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER audit_log_no_delete ON audit_log;
+DROP TRIGGER audit_log_no_update ON audit_log;
+DROP FUNCTION audit_log_append_only();
+DROP TABLE audit_log;
+DROP TABLE room_mutes;
+DROP TABLE room_bans;
+DROP TABLE room_requests;
+DROP TABLE room_invites;
+DROP TABLE room_members;
+DROP TABLE messages;
+DROP TABLE users;
+DROP TABLE rooms;
@@ -0,0 +1,91 @@
+-- Your SQL goes here
+CREATE TABLE rooms (
+  id SERIAL PRIMARY KEY,
+  rname VARCHAR(255) NOT NULL,
+  visibility INT NOT NULL DEFAULT 0,
+  password VARCHAR(64)
+);
+
+CREATE TABLE users (
+  uuid UUID PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  password VARCHAR(64) NOT NULL,
+  permission_id INT NOT NULL,
+  state INT NOT NULL DEFAULT 0,
+  deleted_at TIMESTAMPTZ
+);
+
+CREATE TABLE messages (
+  uuid UUID PRIMARY KEY,
+  time TIMESTAMPTZ NOT NULL,
+  content TEXT NOT NULL,
+  sender_id UUID NOT NULL REFERENCES users(uuid),
+  room_id INT NOT NULL REFERENCES rooms(id)
+);
+
+CREATE TABLE room_members (
+  room_id INT NOT NULL REFERENCES rooms(id),
+  user_id UUID NOT NULL REFERENCES users(uuid),
+  role INT NOT NULL,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_invites (
+  room_id INT NOT NULL REFERENCES rooms(id),
+  user_id UUID NOT NULL REFERENCES users(uuid),
+  inviter_id UUID NOT NULL REFERENCES users(uuid),
+  time TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_requests (
+  room_id INT NOT NULL REFERENCES rooms(id),
+  user_id UUID NOT NULL REFERENCES users(uuid),
+  time TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_bans (
+  room_id INT NOT NULL REFERENCES rooms(id),
+  user_id UUID NOT NULL REFERENCES users(uuid),
+  moderator_id UUID NOT NULL REFERENCES users(uuid),
+  reason TEXT,
+  time TIMESTAMPTZ NOT NULL,
+  expires TIMESTAMPTZ,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_mutes (
+  room_id INT NOT NULL REFERENCES rooms(id),
+  user_id UUID NOT NULL REFERENCES users(uuid),
+  moderator_id UUID NOT NULL REFERENCES users(uuid),
+  time TIMESTAMPTZ NOT NULL,
+  expires TIMESTAMPTZ,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE audit_log (
+  id BIGSERIAL PRIMARY KEY,
+  actor_id UUID NOT NULL,
+  action VARCHAR(64) NOT NULL,
+  target VARCHAR(255),
+  room_id INT,
+  reason TEXT,
+  time TIMESTAMPTZ NOT NULL
+);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+-- messages of erased users are attributed to this account
+INSERT INTO users (uuid, name, password, permission_id, state)
+  VALUES ('00000000-0000-0000-0000-000000000000', 'deleted user', '', 0, 3);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX room_members_user_idx;
+DROP INDEX messages_sender_time_idx;
+DROP INDEX messages_room_time_idx;
+ALTER TABLE rooms DROP CONSTRAINT rooms_rname_key;
+ALTER TABLE users DROP CONSTRAINT users_name_key;
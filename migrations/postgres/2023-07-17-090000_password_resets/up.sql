@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN session_stamp VARCHAR(36) NOT NULL DEFAULT '';
+
+CREATE TABLE password_resets (
+  user_id UUID PRIMARY KEY REFERENCES users(uuid),
+  token VARCHAR(64) NOT NULL,
+  expires TIMESTAMPTZ NOT NULL,
+  CONSTRAINT password_resets_token_key UNIQUE (token)
+);
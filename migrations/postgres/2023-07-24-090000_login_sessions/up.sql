@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE login_sessions (
+  key_digest VARCHAR(64) PRIMARY KEY,
+  id VARCHAR(36) NOT NULL,
+  user_id UUID REFERENCES users(uuid) ON DELETE CASCADE,
+  state TEXT NOT NULL,
+  user_agent VARCHAR(255),
+  ip VARCHAR(45),
+  created TIMESTAMPTZ NOT NULL,
+  last_seen TIMESTAMPTZ NOT NULL,
+  expires TIMESTAMPTZ NOT NULL,
+  CONSTRAINT login_sessions_id_key UNIQUE (id)
+);
+
+CREATE INDEX login_sessions_user_idx ON login_sessions (user_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP CONSTRAINT users_display_key_key;
+ALTER TABLE users DROP COLUMN display_key;
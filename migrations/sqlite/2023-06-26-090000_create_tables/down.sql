@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER audit_log_no_delete;
+DROP TRIGGER audit_log_no_update;
+DROP TABLE audit_log;
+DROP TABLE room_mutes;
+DROP TABLE room_bans;
+DROP TABLE room_requests;
+DROP TABLE room_invites;
+DROP TABLE room_members;
+DROP TABLE messages;
+DROP TABLE users;
+DROP TABLE rooms;
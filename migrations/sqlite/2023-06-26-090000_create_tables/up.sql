@@ -0,0 +1,89 @@
+-- Your SQL goes here
+CREATE TABLE rooms (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  rname VARCHAR(255) NOT NULL,
+  visibility INTEGER NOT NULL DEFAULT 0,
+  password VARCHAR(64)
+);
+
+CREATE TABLE users (
+  uuid CHAR(36) NOT NULL PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  password VARCHAR(64) NOT NULL,
+  permission_id INTEGER NOT NULL,
+  state INTEGER NOT NULL DEFAULT 0,
+  deleted_at TIMESTAMP
+);
+
+CREATE TABLE messages (
+  uuid CHAR(36) NOT NULL PRIMARY KEY,
+  time TIMESTAMP NOT NULL,
+  content TEXT NOT NULL,
+  sender_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  room_id INTEGER NOT NULL REFERENCES rooms(id)
+);
+
+CREATE TABLE room_members (
+  room_id INTEGER NOT NULL REFERENCES rooms(id),
+  user_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  role INTEGER NOT NULL,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_invites (
+  room_id INTEGER NOT NULL REFERENCES rooms(id),
+  user_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  inviter_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  time TIMESTAMP NOT NULL,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_requests (
+  room_id INTEGER NOT NULL REFERENCES rooms(id),
+  user_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  time TIMESTAMP NOT NULL,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_bans (
+  room_id INTEGER NOT NULL REFERENCES rooms(id),
+  user_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  moderator_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  reason TEXT,
+  time TIMESTAMP NOT NULL,
+  expires TIMESTAMP,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE room_mutes (
+  room_id INTEGER NOT NULL REFERENCES rooms(id),
+  user_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  moderator_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  time TIMESTAMP NOT NULL,
+  expires TIMESTAMP,
+  PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  actor_id CHAR(36) NOT NULL,
+  action VARCHAR(64) NOT NULL,
+  target VARCHAR(255),
+  room_id INTEGER,
+  reason TEXT,
+  time TIMESTAMP NOT NULL
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+-- messages of erased users are attributed to this account
+INSERT INTO users (uuid, name, password, permission_id, state)
+  VALUES ('00000000-0000-0000-0000-000000000000', 'deleted user', '', 0, 3);
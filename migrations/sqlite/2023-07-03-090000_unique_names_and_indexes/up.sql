@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- rename duplicates left over from before names were unique, the oldest
+-- account or room keeps its name
+UPDATE users SET name = name || '#' || uuid
+  WHERE uuid <> (SELECT MIN(u.uuid) FROM users u WHERE u.name = users.name);
+UPDATE rooms SET rname = rname || '#' || id
+  WHERE id <> (SELECT MIN(r.id) FROM rooms r WHERE r.rname = rooms.rname);
+
+CREATE UNIQUE INDEX users_name_key ON users (name);
+CREATE UNIQUE INDEX rooms_rname_key ON rooms (rname);
+
+CREATE INDEX messages_room_time_idx ON messages (room_id, time);
+CREATE INDEX messages_sender_time_idx ON messages (sender_id, time);
+CREATE INDEX room_members_user_idx ON room_members (user_id);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE rooms ADD COLUMN archived_at TIMESTAMP;
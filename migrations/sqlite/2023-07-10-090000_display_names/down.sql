@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_display_name_key;
+ALTER TABLE users DROP COLUMN display_name;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN display_name VARCHAR(32);
+CREATE UNIQUE INDEX users_display_name_key ON users (display_name);
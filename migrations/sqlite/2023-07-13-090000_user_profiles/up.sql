@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE user_profiles (
+  user_id CHAR(36) NOT NULL PRIMARY KEY REFERENCES users(uuid),
+  avatar VARCHAR(64),
+  bio TEXT,
+  status VARCHAR(128),
+  timezone VARCHAR(64)
+);
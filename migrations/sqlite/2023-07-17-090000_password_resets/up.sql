@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN session_stamp VARCHAR(36) NOT NULL DEFAULT '';
+
+CREATE TABLE password_resets (
+  user_id CHAR(36) NOT NULL PRIMARY KEY REFERENCES users(uuid),
+  token CHAR(64) NOT NULL,
+  expires TIMESTAMP NOT NULL
+);
+CREATE UNIQUE INDEX password_resets_token_key ON password_resets (token);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE user_totp (
+  user_id CHAR(36) NOT NULL PRIMARY KEY REFERENCES users(uuid),
+  secret VARCHAR(64) NOT NULL,
+  enabled_at TIMESTAMP,
+  last_step BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE recovery_codes (
+  user_id CHAR(36) NOT NULL REFERENCES users(uuid),
+  code CHAR(64) NOT NULL,
+  PRIMARY KEY (user_id, code)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_display_key_key;
+ALTER TABLE users DROP COLUMN display_key;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- display names compared without case, so "Admin" can't pose as "admin"
+ALTER TABLE users ADD COLUMN display_key VARCHAR(32);
+UPDATE users SET display_key = LOWER(display_name) WHERE display_name IS NOT NULL;
+CREATE UNIQUE INDEX users_display_key_key ON users (display_key);
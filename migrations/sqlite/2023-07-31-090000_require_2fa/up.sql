@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- set by admins, the user needs two factor authentication whatever their role
+ALTER TABLE users ADD COLUMN require_2fa BOOLEAN NOT NULL DEFAULT 0;
@@ -1,13 +1,20 @@
 use crate::{
-    models::Pool,
-    query,
+    config::Config,
+    events::{self, SystemEvent},
+    login_session::{self, LoginSessions},
+    models::{User, UserState},
+    password::{self, PasswordError},
+    profile::{self, ProfileError, ProfilePatch},
+    store::{AuditFilter, ChatStore, QueryError},
+    two_factor::{self, TwoFactorError},
 };
 use actix::Addr;
 use actix_files::NamedFile;
 use actix_http::HttpMessage;
 use actix_identity::Identity;
+use actix_session::Session;
 use actix_web::{
-    cookie::Key, http::StatusCode, web, Error,
+    cookie::Key, http::{header, StatusCode}, web, Either, Error,
     HttpRequest, HttpResponse, Responder, Result,
 };
 use actix_web_actors::ws;
@@ -16,56 +23,130 @@ use std::{
     sync::{
         atomic::{AtomicUsize, Ordering},
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use sha256::digest;
 
 use crate::server;
 use crate::session;
+use crate::throttle::{Bucket, RateLimits, Throttle};
 
-pub async fn index() -> NamedFile {
-    NamedFile::open_async("./static/index.html").await.unwrap()
+pub async fn index(config: web::Data<Config>) -> NamedFile {
+    NamedFile::open_async(config.static_file("index.html")).await.unwrap()
 }
 
-pub async fn rigister() -> NamedFile {
-    NamedFile::open_async("./static/rigister.html")
+pub async fn rigister(config: web::Data<Config>) -> NamedFile {
+    NamedFile::open_async(config.static_file("rigister.html"))
         .await
         .unwrap()
 }
 
-pub fn secret_key() -> Key {
-    Key::generate()
+/// Key the session cookies are encrypted with, a random one unless the
+/// configuration has a secret
+pub fn secret_key(secret: Option<&str>) -> Key {
+    match secret {
+        Some(secret) => Key::from(secret.as_bytes()),
+        None => Key::generate(),
+    }
+}
+
+
+/// The logged in user, if the account is active and the password wasn't
+/// changed since the login. Stale logins are logged out.
+async fn authenticate(
+    store: &web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: &Session,
+) -> Result<Option<User>, QueryError> {
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    let Ok(id) = user.id() else {
+        return Ok(None);
+    };
+    let store = store.clone();
+    let value = web::block(move || store.query_user_from_id(&id)).await??;
+    let stamp = session.get::<String>(login_session::STAMP_KEY).ok().flatten();
+    match value {
+        Some(value)
+            if UserState::from_i32(value.state) == UserState::Active
+                && stamp.as_deref() == Some(value.session_stamp.as_str()) =>
+        {
+            Ok(Some(value))
+        }
+        _ => {
+            user.logout();
+            Ok(None)
+        }
+    }
 }
 
-pub async fn chatroom() -> impl Responder {
-    NamedFile::open_async("./static/chatroom.html")
+/// Refuse users whose role requires two factor authentication they haven't
+/// set up yet, they may only log in to set it up
+async fn require_two_factor(
+    store: &web::Data<dyn ChatStore>,
+    config: &web::Data<Config>,
+    user: &User,
+) -> Result<(), TwoFactorError> {
+    let (store, config, user) = (store.clone(), config.clone(), user.clone());
+    if web::block(move || two_factor::missing(store.get_ref(), &config, &user)).await?? {
+        return Err(TwoFactorError::Required);
+    }
+    Ok(())
+}
+
+pub async fn chatroom(config: web::Data<Config>) -> impl Responder {
+    NamedFile::open_async(config.static_file("chatroom.html"))
         .await
         .unwrap()
 }
 
 /// Entry point for our websocket route
+#[allow(clippy::too_many_arguments)]
 pub async fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<server::ChatServer>>,
     user: Option<Identity>,
-    pool: web::Data<Pool>,
+    session: Session,
+    store: web::Data<dyn ChatStore>,
+    join_throttle: web::Data<Throttle>,
+    rate_limits: web::Data<RateLimits>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
+    let user = authenticate(&store, user, &session).await?;
+    if user.is_none() {
+        return Ok(HttpResponse::new(StatusCode::FORBIDDEN));
+    }
+    if let Some(ref user) = user {
+        require_two_factor(&store, &config, user).await?;
+    }
     if let Some(user) = user {
-        format!("Welcome! {}", user.id().unwrap());
-        ws::start(
+        format!("Welcome! {}", user.uuid);
+        let frame_size = config.max_frame_size;
+        let login = current_session(&session);
+        ws::WsResponseBuilder::new(
             session::WsChatSession {
-                id: user.id().unwrap(),
+                id: user.uuid,
+                login,
                 hb: Instant::now(),
-                room: "main".to_owned(),
-                name: None,
+                room: config.default_room.clone(),
                 addr: srv.get_ref().clone(),
-                db_pool: pool,
+                shard: None,
+                store,
+                join_throttle,
+                messages: Bucket::new(&config.limits.session_messages),
+                commands: Bucket::new(&config.limits.commands),
+                strikes: 0,
+                rate_limits,
+                config,
             },
             &req,
             stream,
         )
+        .frame_size(frame_size)
+        .start()
     } else {
         Ok(HttpResponse::new(StatusCode::NON_AUTHORITATIVE_INFORMATION))
     }
@@ -83,27 +164,568 @@ pub struct LoginInfo {
     password: String,
 }
 
+/// Take a token of the client address and one of the user name, logins
+/// and registrations share them
+fn login_limit(limits: &RateLimits, request: &HttpRequest, name: &str) -> Result<(), Duration> {
+    if let Some(addr) = request.peer_addr() {
+        limits.login_ip.take(&addr.ip().to_string())?;
+    }
+    limits.login_user.take(name)
+}
+
+/// 429 telling the client when to try again
+fn slow_down(wait: Duration) -> HttpResponse {
+    let secs = wait.as_secs_f64().ceil() as u64;
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, secs.to_string()))
+        .body(format!("slow down, try again in {secs} seconds"))
+}
+
 pub async fn login(
-    pool: web::Data<Pool>,
+    store: web::Data<dyn ChatStore>,
+    rate_limits: web::Data<RateLimits>,
     params: web::Form<LoginInfo>,
     request: HttpRequest,
+    session: Session,
 ) -> Result<impl Responder, Error> {
     let params = params.into_inner();
     let user_na = &params.username;
     let pass_wo = &params.password;
+    if let Err(wait) = login_limit(&rate_limits, &request, user_na) {
+        log::info!("[{user_na}]:login rate limited");
+        return Ok(Either::Left(slow_down(wait)));
+    }
     log::info!("[{user_na}]:logging");
-    if let Some(value) = query::query_user(user_na, pool) {
-        if value.password.as_str() == digest(pass_wo.as_str()) {
+    let name = user_na.clone();
+    let db_store = store.clone();
+    if let Some(value) = web::block(move || db_store.query_user(&name)).await?? {
+        let state = UserState::from_i32(value.state);
+        if state == UserState::Suspended || state == UserState::Deleted {
+            log::info!("[{user_na}]:login refused, account {state:?}");
+        } else if value.password.as_str() == digest(pass_wo.as_str()) {
+            let uuid = value.uuid.clone();
+            let db_store = store.clone();
+            if web::block(move || two_factor::enabled(db_store.get_ref(), &uuid)).await?? {
+                // not logged in before the second factor is checked too
+                log::info!("[{user_na}]:password accepted, waiting for code");
+                session.insert(PENDING_KEY, (&value.uuid, chrono::Utc::now().timestamp()))?;
+                return Ok(Either::Right(web::Redirect::to("/login/2fa").using_status_code(StatusCode::FOUND)));
+            }
             log::info!("[{user_na}]:login sucess");
-            Identity::login(&request.extensions_mut(), value.uuid.into()).unwrap();
+            finish_login(&store, &request, &session, value).await?;
         } else {
-            println!("{}", value.password.as_str());
-            println!("{}", digest(pass_wo.as_str()));
+            log::info!("[{user_na}]:login failed");
         }
     } else {
         log::info!("[{user_na}]:login failed");
     }
-    Ok(web::Redirect::to("/chatroom").using_status_code(StatusCode::FOUND))
+    Ok(Either::Right(web::Redirect::to("/chatroom").using_status_code(StatusCode::FOUND)))
+}
+
+/// Session key of the user id and time of a login waiting for its second
+/// factor
+const PENDING_KEY: &str = "pending_2fa";
+
+/// Seconds the second factor of a login may take
+const PENDING_SECS: i64 = 300;
+
+/// Log the user in, a deactivated account is active again
+async fn finish_login(
+    store: &web::Data<dyn ChatStore>,
+    request: &HttpRequest,
+    session: &Session,
+    user: User,
+) -> Result<(), Error> {
+    if UserState::from_i32(user.state) == UserState::Deactivated {
+        let store = store.clone();
+        let uuid = user.uuid.clone();
+        web::block(move || store.update_user_state(&uuid, UserState::Active)).await??;
+        log::info!("[{}]:reactivated", user.name);
+    }
+    Identity::login(&request.extensions_mut(), user.uuid).unwrap();
+    session.insert(login_session::STAMP_KEY, &user.session_stamp)?;
+    // shown in the user's list of sessions
+    if let Some(agent) = request.headers().get(header::USER_AGENT).and_then(|agent| agent.to_str().ok()) {
+        session.insert(login_session::USER_AGENT_KEY, agent)?;
+    }
+    if let Some(addr) = request.peer_addr() {
+        session.insert(login_session::IP_KEY, addr.ip().to_string())?;
+    }
+    Ok(())
+}
+
+pub async fn login_2fa(config: web::Data<Config>) -> NamedFile {
+    NamedFile::open_async(config.static_file("two_factor.html"))
+        .await
+        .unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SecondFactor {
+    code: String,
+}
+
+/// Second step of a login, a TOTP or recovery code of the user whose
+/// password was accepted
+pub async fn login_2fa_post(
+    store: web::Data<dyn ChatStore>,
+    rate_limits: web::Data<RateLimits>,
+    params: web::Form<SecondFactor>,
+    request: HttpRequest,
+    session: Session,
+) -> Result<impl Responder, Error> {
+    let pending = session.get::<(String, i64)>(PENDING_KEY).ok().flatten();
+    let now = chrono::Utc::now().timestamp();
+    let Some((id, _)) = pending.filter(|&(_, since)| now - since <= PENDING_SECS) else {
+        session.remove(PENDING_KEY);
+        return Ok(Either::Right(web::Redirect::to("/").using_status_code(StatusCode::FOUND)));
+    };
+    // six digits are guessed quickly otherwise
+    if let Err(wait) = login_limit(&rate_limits, &request, &id) {
+        log::info!("[{id}]:second factor rate limited");
+        return Ok(Either::Left(slow_down(wait)));
+    }
+    let code = params.into_inner().code;
+    let db_store = store.clone();
+    let user = web::block(move || two_factor::verify_login(db_store.get_ref(), &id, &code)).await??;
+    match user {
+        Some(user)
+            if !matches!(
+                UserState::from_i32(user.state),
+                UserState::Suspended | UserState::Deleted
+            ) =>
+        {
+            log::info!("[{}]:login sucess", user.name);
+            session.remove(PENDING_KEY);
+            finish_login(&store, &request, &session, user).await?;
+            Ok(Either::Right(web::Redirect::to("/chatroom").using_status_code(StatusCode::FOUND)))
+        }
+        _ => {
+            log::info!("second factor refused");
+            Ok(Either::Right(web::Redirect::to("/login/2fa").using_status_code(StatusCode::FOUND)))
+        }
+    }
+}
+
+/// Id of the login session of the request, see `login_session`
+fn current_session(session: &Session) -> Option<String> {
+    session.get::<String>(login_session::SESSION_ID_KEY).ok().flatten()
+}
+
+/// End the login session, its websockets are closed
+pub async fn logout(user: Option<Identity>, session: Session) -> impl Responder {
+    if let Some(user) = user {
+        if let (Ok(id), Some(login)) = (user.id(), current_session(&session)) {
+            log::info!("[{id}]:logged out");
+            events::issue(SystemEvent::SessionRevoked { id, session: login });
+        }
+        user.logout();
+    }
+    web::Redirect::to("/").using_status_code(StatusCode::FOUND)
+}
+
+/// Login sessions of the logged in user: device, address and last use
+pub async fn list_sessions(
+    store: web::Data<dyn ChatStore>,
+    sessions: web::Data<LoginSessions>,
+    user: Option<Identity>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    let Some(user) = authenticate(&store, user, &session).await? else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let current = current_session(&session);
+    let list = web::block(move || sessions.list(&user.uuid)).await??;
+    let list: Vec<_> = list
+        .into_iter()
+        .map(|login| {
+            serde_json::json!({
+                "id": login.id,
+                "current": current.as_deref() == Some(login.id.as_str()),
+                "user_agent": login.user_agent,
+                "ip": login.ip,
+                "created": login.created,
+                "last_seen": login.last_seen,
+                "expires": login.expires,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "sessions": list })))
+}
+
+/// End one of the own login sessions, it is logged out and its websockets
+/// are closed
+pub async fn revoke_session(
+    store: web::Data<dyn ChatStore>,
+    sessions: web::Data<LoginSessions>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let Some(identity) = user else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let Ok(id) = identity.id() else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    if authenticate(&store, Some(identity), &session).await?.is_none() {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let login = path.into_inner();
+    let (user_id, target) = (id.clone(), login.clone());
+    if !web::block(move || sessions.revoke(&user_id, &target)).await?? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    log::info!("[{id}]:session {login} revoked");
+    events::issue(SystemEvent::SessionRevoked { id, session: login });
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Browse the moderation audit log, admins only
+pub async fn audit_log(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    filter: web::Query<AuditFilter>,
+    page: web::Query<Page>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&store, user, &session)
+        .await?
+        .filter(|user| user.permission_id == 1);
+    let Some(admin) = admin else {
+        return Ok(HttpResponse::Forbidden().finish());
+    };
+    require_two_factor(&store, &config, &admin).await?;
+
+    let page_no = page.page.unwrap_or(1).max(1);
+    let per_page = page.per_page.unwrap_or(50).clamp(1, 200);
+    let filter = filter.into_inner();
+    if let Some(actor) = &filter.actor {
+        if uuid::Uuid::parse_str(actor).is_err() {
+            return Ok(HttpResponse::BadRequest().body("actor is not a user id"));
+        }
+    }
+    let (entries, total) =
+        web::block(move || store.query_audit(&filter, (page_no - 1) * per_page, per_page)).await??;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "entries": entries,
+        "page": page_no,
+        "per_page": per_page,
+        "total": total,
+    })))
+}
+
+/// User id of a path, ids that aren't UUIDs name no user
+fn user_path(path: web::Path<String>) -> Option<String> {
+    let id = path.into_inner();
+    uuid::Uuid::parse_str(&id).is_ok().then_some(id)
+}
+
+/// Id of the logged in user
+async fn logged_in(
+    store: &web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: &Session,
+) -> Result<String, ProfileError> {
+    match authenticate(store, user, session).await? {
+        Some(user) => Ok(user.uuid),
+        None => Err(ProfileError::Unauthorized),
+    }
+}
+
+/// Profile of a user as other users see it
+pub async fn get_profile(
+    store: web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ProfileError> {
+    logged_in(&store, user, &session).await?;
+    let id = user_path(path).ok_or(ProfileError::NotFound)?;
+    let (user, profile) = web::block(move || profile::load(store.get_ref(), &id)).await??;
+    Ok(HttpResponse::Ok().json(profile::public(&user, &profile)))
+}
+
+/// Change a profile, the rooms the user is in are told
+pub async fn patch_profile(
+    store: web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+    patch: web::Json<ProfilePatch>,
+) -> Result<HttpResponse, ProfileError> {
+    let actor = logged_in(&store, user, &session).await?;
+    let id = user_path(path).ok_or(ProfileError::NotFound)?;
+    let patch = patch.into_inner();
+    let (user, profile) = web::block(move || profile::update(store.get_ref(), &actor, &id, patch)).await??;
+    log::info!("[{}]:profile changed", user.uuid);
+    events::issue(profile::changed(&user, &profile));
+    Ok(HttpResponse::Ok().json(profile::public(&user, &profile)))
+}
+
+/// Upload an avatar, the body is the image. It is cropped to a square and
+/// resized to `avatar_size`.
+pub async fn put_avatar(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ProfileError> {
+    let id = user_path(path).ok_or(ProfileError::NotFound)?;
+    set_avatar(store, config, user, session, id, Some(body)).await
+}
+
+pub async fn delete_avatar(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ProfileError> {
+    let id = user_path(path).ok_or(ProfileError::NotFound)?;
+    set_avatar(store, config, user, session, id, None).await
+}
+
+async fn set_avatar(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    id: String,
+    body: Option<web::Bytes>,
+) -> Result<HttpResponse, ProfileError> {
+    let actor = logged_in(&store, user, &session).await?;
+    let (user, profile) = web::block(move || {
+        profile::replace_avatar(store.get_ref(), &config, &actor, &id, body.as_deref())
+    })
+    .await??;
+    log::info!("[{}]:avatar changed", user.uuid);
+    events::issue(profile::changed(&user, &profile));
+    Ok(HttpResponse::Ok().json(profile::public(&user, &profile)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordChange {
+    current: String,
+    password: String,
+}
+
+/// Change the own password. Other logins of the user are void afterwards
+/// and their websockets are closed, this login stays.
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    sessions: web::Data<LoginSessions>,
+    rate_limits: web::Data<RateLimits>,
+    user: Option<Identity>,
+    session: Session,
+    request: HttpRequest,
+    params: web::Json<PasswordChange>,
+) -> Result<HttpResponse, PasswordError> {
+    let Some(user) = authenticate(&store, user, &session).await? else {
+        return Err(PasswordError::Unauthorized);
+    };
+    // guessing the current password is held back like logins are
+    if let Err(wait) = login_limit(&rate_limits, &request, &user.name) {
+        log::info!("[{}]:password change rate limited", user.name);
+        return Ok(slow_down(wait));
+    }
+    let params = params.into_inner();
+    let id = user.uuid;
+    let user =
+        web::block(move || password::change(store.get_ref(), &config, &id, &params.current, &params.password))
+            .await??;
+    if let Err(err) = session.insert(login_session::STAMP_KEY, &user.session_stamp) {
+        log::warn!("[{}]:session stamp not updated: {err}", user.name);
+    }
+    let (id, keep) = (user.uuid.clone(), current_session(&session));
+    web::block(move || sessions.revoke_all(&id, keep.as_deref())).await??;
+    log::info!("[{}]:password changed", user.name);
+    events::issue(SystemEvent::PasswordChanged { id: user.uuid });
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Hand out a one time password reset token for a user, admins only
+pub async fn issue_password_reset(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let Some(actor) = authenticate(&store, user, &session).await? else {
+        return Err(PasswordError::Unauthorized.into());
+    };
+    require_two_factor(&store, &config, &actor).await?;
+    let id = user_path(path).ok_or(PasswordError::NotFound)?;
+    let (token, reset) =
+        web::block(move || password::issue_reset(store.get_ref(), &config, &actor.uuid, &id)).await??;
+    log::info!("[{}]:password reset issued", reset.user_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "expires": reset.expires,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetInfo {
+    token: String,
+    password: String,
+}
+
+/// Set a new password with a reset token, every login of the user is void
+/// afterwards
+pub async fn reset_password(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    sessions: web::Data<LoginSessions>,
+    rate_limits: web::Data<RateLimits>,
+    request: HttpRequest,
+    params: web::Json<PasswordResetInfo>,
+) -> Result<HttpResponse, PasswordError> {
+    if let Some(addr) = request.peer_addr() {
+        if let Err(wait) = rate_limits.login_ip.take(&addr.ip().to_string()) {
+            return Ok(slow_down(wait));
+        }
+    }
+    let params = params.into_inner();
+    let user = web::block(move || password::redeem(store.get_ref(), &config, &params.token, &params.password))
+        .await??;
+    let id = user.uuid.clone();
+    web::block(move || sessions.revoke_all(&id, None)).await??;
+    log::info!("[{}]:password reset", user.name);
+    events::issue(SystemEvent::PasswordChanged { id: user.uuid });
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The logged in user
+async fn two_factor_user(
+    store: &web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: &Session,
+) -> Result<User, TwoFactorError> {
+    authenticate(store, user, session)
+        .await?
+        .ok_or(TwoFactorError::Unauthorized)
+}
+
+/// Start setting up two factor authentication. The secret and its
+/// provisioning URI go into an authenticator app, it is enabled once one
+/// of its codes is confirmed.
+pub async fn enroll_2fa(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user = two_factor_user(&store, user, &session).await?;
+    let (secret, uri) = web::block(move || two_factor::enroll(store.get_ref(), &config, &user)).await??;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "secret": secret,
+        "uri": uri,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCode {
+    code: String,
+}
+
+/// Enable two factor authentication with a code of the new secret. The
+/// recovery codes in the response are not shown again.
+pub async fn confirm_2fa(
+    store: web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: Session,
+    params: web::Json<TwoFactorCode>,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user = two_factor_user(&store, user, &session).await?;
+    let name = user.name.clone();
+    let codes = web::block(move || two_factor::confirm(store.get_ref(), &user, &params.code)).await??;
+    log::info!("[{name}]:two factor authentication enabled");
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "recovery_codes": codes })))
+}
+
+/// Replace the recovery codes, the old ones stop working
+pub async fn regenerate_recovery_codes(
+    store: web::Data<dyn ChatStore>,
+    user: Option<Identity>,
+    session: Session,
+    params: web::Json<TwoFactorCode>,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user = two_factor_user(&store, user, &session).await?;
+    let name = user.name.clone();
+    let codes = web::block(move || two_factor::regenerate(store.get_ref(), &user, &params.code)).await??;
+    log::info!("[{name}]:recovery codes replaced");
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "recovery_codes": codes })))
+}
+
+/// Turn the own two factor authentication off, not allowed where the role
+/// requires it
+pub async fn disable_2fa(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    params: web::Json<TwoFactorCode>,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user = two_factor_user(&store, user, &session).await?;
+    let name = user.name.clone();
+    web::block(move || two_factor::disable(store.get_ref(), &config, &user, &params.code)).await??;
+    log::info!("[{name}]:two factor authentication disabled");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Turn two factor authentication off for a user who lost access to it,
+/// admins only
+pub async fn reset_2fa(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+) -> Result<HttpResponse, TwoFactorError> {
+    let actor = two_factor_user(&store, user, &session).await?;
+    require_two_factor(&store, &config, &actor).await?;
+    let id = user_path(path).ok_or(TwoFactorError::NotFound)?;
+    let target = id.clone();
+    web::block(move || two_factor::reset(store.get_ref(), &actor, &target)).await??;
+    log::info!("[{id}]:two factor authentication reset");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorRequired {
+    required: bool,
+}
+
+/// Require two factor authentication of a user whatever their role, or
+/// leave it to the configuration again, admins only
+pub async fn require_2fa(
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    user: Option<Identity>,
+    session: Session,
+    path: web::Path<String>,
+    params: web::Json<TwoFactorRequired>,
+) -> Result<HttpResponse, TwoFactorError> {
+    let actor = two_factor_user(&store, user, &session).await?;
+    require_two_factor(&store, &config, &actor).await?;
+    let id = user_path(path).ok_or(TwoFactorError::NotFound)?;
+    let (target, required) = (id.clone(), params.required);
+    web::block(move || two_factor::set_required(store.get_ref(), &actor, &target, required)).await??;
+    log::info!("[{id}]:two factor authentication required: {required}");
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[derive(Debug, Deserialize)]
@@ -113,18 +735,45 @@ pub struct RigisterInfo {
 }
 
 pub async fn rigister_post(
-    pool: web::Data<Pool>,
+    store: web::Data<dyn ChatStore>,
+    config: web::Data<Config>,
+    rate_limits: web::Data<RateLimits>,
     params: web::Form<RigisterInfo>,
+    request: HttpRequest,
 ) -> Result<impl Responder, Error> {
     let params = params.into_inner();
     let user_na = &params.username;
     let pass_wo = &params.password;
+    if let Err(wait) = login_limit(&rate_limits, &request, user_na) {
+        log::info!("[{user_na}]:rigister rate limited");
+        return Ok(Either::Left(slow_down(wait)));
+    }
+    if let Err(err) = password::check(user_na, pass_wo, config.min_password_length) {
+        log::info!("[{user_na}]:rigister refused, {err}");
+        return Ok(Either::Right(web::Redirect::to("/rigister").using_status_code(StatusCode::FOUND)));
+    }
     log::info!("[{user_na}]:rigistering");
-    if let Some(value) = query::query_user(user_na, pool.clone()) {
-        log::info!("[{}]:have been used", value.name);
-        return Ok(web::Redirect::to("/rigister").using_status_code(StatusCode::FOUND));
+    let name = user_na.clone();
+    let db_store = store.clone();
+    let taken = web::block(move || -> Result<bool, QueryError> {
+        // someone shown under that name could be impersonated otherwise
+        Ok(!db_store.query_users_named(&name)?.is_empty())
+    });
+    if taken.await?? {
+        log::info!("[{user_na}]:have been used");
+        return Ok(Either::Right(web::Redirect::to("/rigister").using_status_code(StatusCode::FOUND)));
     } else {
-        query::insert_user(user_na, &digest(pass_wo.to_owned()), pool).expect("Fail to insert user");
+        let name = user_na.clone();
+        let password = digest(pass_wo.to_owned());
+        match web::block(move || store.insert_user(&name, &password)).await? {
+            Ok(_) => {}
+            // registered concurrently under the same name
+            Err(QueryError::Conflict) => {
+                log::info!("[{user_na}]:have been used");
+                return Ok(Either::Right(web::Redirect::to("/rigister").using_status_code(StatusCode::FOUND)));
+            }
+            Err(err) => return Err(err.into()),
+        }
     }
-    Ok(web::Redirect::to("/").using_status_code(StatusCode::FOUND))
+    Ok(Either::Right(web::Redirect::to("/").using_status_code(StatusCode::FOUND)))
 }
@@ -0,0 +1,216 @@
+//! Fan out of chat events between server instances. Every `ChatServer`
+//! delivers events to its own sessions and publishes them through the
+//! broker, the other nodes deliver them to theirs.
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::events::SystemEvent;
+
+/// Pause before a lost broker connection is opened again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Something that happened on one node the others have to know about
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Text for every session in `room` except `skip_id`
+    Message { room: String, msg: String, skip_id: String },
+    /// A room was opened or its visibility changed
+    Room { name: String, hidden: bool },
+    /// Remove a session from a room, whichever node it is on
+    Kick { id: String, room: String, reason: String },
+    /// An event of the node's own event bus
+    System { event: SystemEvent },
+}
+
+/// An event together with the node it comes from
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct Envelope {
+    pub node: String,
+    pub event: Event,
+}
+
+pub trait Broker: Send + Sync {
+    /// Hand an event to every subscribed node. Delivery is best effort,
+    /// events published while the broker is unreachable are lost.
+    fn publish(&self, envelope: &Envelope);
+    /// Deliver published events to `recipient`, its own ones included
+    fn subscribe(&self, recipient: Recipient<Envelope>);
+}
+
+/// Broker between chat servers of the same process, the only one needed
+/// for a single instance
+#[derive(Default)]
+pub struct LocalBroker {
+    subscribers: Mutex<Vec<Recipient<Envelope>>>,
+}
+
+impl Broker for LocalBroker {
+    fn publish(&self, envelope: &Envelope) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|recipient| recipient.connected());
+        for recipient in subscribers.iter() {
+            recipient.do_send(envelope.clone());
+        }
+    }
+
+    fn subscribe(&self, recipient: Recipient<Envelope>) {
+        self.subscribers.lock().unwrap().push(recipient);
+    }
+}
+
+/// Redis pub/sub on one channel. The blocking client runs on threads of
+/// its own so neither the actors nor the workers wait on the network.
+pub struct RedisBroker {
+    client: redis::Client,
+    channel: String,
+    outgoing: mpsc::Sender<String>,
+}
+
+impl RedisBroker {
+    pub fn connect(url: &str, channel: &str) -> Result<RedisBroker, String> {
+        let client = redis::Client::open(url).map_err(|err| format!("invalid broker url {url}: {err}"))?;
+        // fail at startup rather than on the first message
+        client.get_connection().map_err(|err| format!("can't reach broker {url}: {err}"))?;
+
+        let (outgoing, rx) = mpsc::channel::<String>();
+        let publisher = client.clone();
+        let publish_channel = channel.to_owned();
+        thread::Builder::new()
+            .name("broker-publish".to_owned())
+            .spawn(move || {
+                let mut conn = None;
+                for payload in rx {
+                    if conn.is_none() {
+                        conn = publisher.get_connection().ok();
+                    }
+                    let Some(ref mut c) = conn else {
+                        log::error!("broker unreachable, dropped an event");
+                        continue;
+                    };
+                    let sent = redis::cmd("PUBLISH")
+                        .arg(&publish_channel)
+                        .arg(payload)
+                        .query::<i64>(c);
+                    if let Err(err) = sent {
+                        log::error!("failed to publish to the broker: {err}");
+                        conn = None;
+                    }
+                }
+            })
+            .map_err(|err| err.to_string())?;
+
+        Ok(RedisBroker {
+            client,
+            channel: channel.to_owned(),
+            outgoing,
+        })
+    }
+}
+
+/// Forward messages of `channel` to `recipient` until the connection fails
+fn listen(client: &redis::Client, channel: &str, recipient: &Recipient<Envelope>) -> redis::RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let mut pubsub = conn.as_pubsub();
+    pubsub.subscribe(channel)?;
+    loop {
+        let payload: String = pubsub.get_message()?.get_payload()?;
+        match serde_json::from_str::<Envelope>(&payload) {
+            Ok(envelope) => recipient.do_send(envelope),
+            Err(err) => log::warn!("ignored malformed broker event: {err}"),
+        }
+    }
+}
+
+impl Broker for RedisBroker {
+    fn publish(&self, envelope: &Envelope) {
+        match serde_json::to_string(envelope) {
+            Ok(payload) => {
+                let _ = self.outgoing.send(payload);
+            }
+            Err(err) => log::error!("failed to encode broker event: {err}"),
+        }
+    }
+
+    fn subscribe(&self, recipient: Recipient<Envelope>) {
+        let client = self.client.clone();
+        let channel = self.channel.clone();
+        let spawned = thread::Builder::new()
+            .name("broker-subscribe".to_owned())
+            .spawn(move || {
+                while recipient.connected() {
+                    if let Err(err) = listen(&client, &channel, &recipient) {
+                        log::error!("broker subscription lost: {err}");
+                    }
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            });
+        if let Err(err) = spawned {
+            log::error!("failed to subscribe to the broker: {err}");
+        }
+    }
+}
+
+/// Open the broker `url` points at, `None` keeps events in this process:
+///
+/// * `redis://host:6379/`, events go through the pub/sub `channel`
+pub fn connect(url: Option<&str>, channel: &str) -> Result<Arc<dyn Broker>, String> {
+    match url {
+        None => Ok(Arc::new(LocalBroker::default())),
+        Some(url) if url.starts_with("redis://") => Ok(Arc::new(RedisBroker::connect(url, channel)?)),
+        Some(url) => Err(format!("unsupported broker url: {url}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{channel::mpsc, StreamExt};
+
+    use super::*;
+
+    /// Forwards the envelopes it gets to the test
+    struct Collector(mpsc::UnboundedSender<Envelope>);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Envelope> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, msg: Envelope, _: &mut Context<Self>) {
+            self.0.unbounded_send(msg).unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn local_broker_fans_out() {
+        let broker = LocalBroker::default();
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let (tx, rx) = mpsc::unbounded();
+            broker.subscribe(Collector(tx).start().recipient());
+            received.push(rx);
+        }
+        broker.publish(&Envelope {
+            node: "node".to_owned(),
+            event: Event::Room {
+                name: "lobby".to_owned(),
+                hidden: false,
+            },
+        });
+        for rx in &mut received {
+            let envelope = rx.next().await.unwrap();
+            assert_eq!(envelope.node, "node");
+            assert!(matches!(envelope.event, Event::Room { ref name, hidden: false } if name == "lobby"));
+        }
+    }
+}
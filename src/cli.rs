@@ -0,0 +1,327 @@
+//! Command line of the binary. Besides serving it offers admin subcommands
+//! working on the database directly, e.g. to create the first admin.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use sha256::digest;
+
+use crate::config::{Config, Overrides};
+use crate::models::{self, AuditEntry, UserState, Visibility};
+use crate::migrate;
+use crate::password;
+use crate::store::{ChatStore, QueryError};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub overrides: Overrides,
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+#[derive(Subcommand)]
+pub enum Action {
+    /// Run the chat server, the default without a subcommand
+    Serve,
+    #[command(flatten)]
+    Admin(AdminAction),
+}
+
+/// Subcommands working on the database instead of serving
+#[derive(Subcommand)]
+pub enum AdminAction {
+    /// Show, apply or revert the database migrations
+    #[command(subcommand)]
+    Migrate(MigrateAction),
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserAction),
+    /// Manage rooms
+    #[command(subcommand)]
+    Room(RoomAction),
+    /// Write users, rooms and their messages as JSON
+    Export {
+        /// Only export this room
+        #[arg(long)]
+        room: Option<String>,
+        /// File to write to instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MigrateAction {
+    /// List every migration and whether it is applied
+    Status,
+    /// Apply the pending migrations
+    Up,
+    /// Revert the latest applied migration
+    Down,
+}
+
+#[derive(Subcommand)]
+pub enum UserAction {
+    /// Create an account, the password is read from stdin
+    Create {
+        name: String,
+        /// Give the account admin permission
+        #[arg(long)]
+        admin: bool,
+    },
+    /// List every account
+    List,
+    /// Make an account an admin or a regular user
+    SetRole { name: String, role: Role },
+    /// Set a new password, read from stdin
+    ResetPassword { name: String },
+    /// Delete an account, it gets erased after the grace period
+    Delete { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum RoomAction {
+    /// List every room
+    List,
+    /// Create a room
+    Create {
+        name: String,
+        #[arg(long, default_value = "public", value_parser = ["public", "private", "invite"])]
+        visibility: String,
+        /// Account owning the room
+        #[arg(long)]
+        owner: Option<String>,
+    },
+    /// Archive a room, it keeps its history but can't be joined or written to
+    Archive {
+        name: String,
+        /// Restore an archived room instead
+        #[arg(long)]
+        undo: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    fn permission_id(self) -> i32 {
+        match self {
+            Role::User => 0,
+            Role::Admin => 1,
+        }
+    }
+}
+
+fn other<E: std::fmt::Display>(err: E) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+/// Read the password of `name` from the first line of stdin, prompting
+/// when it is a terminal, it has to meet the password policy. The input is
+/// echoed, pipe it in where that matters.
+fn read_password(name: &str, config: &Config) -> io::Result<String> {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        eprint!("password: ");
+        io::stderr().flush()?;
+    }
+    let mut line = String::new();
+    stdin.lock().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    password::check(name, password, config.min_password_length).map_err(other)?;
+    Ok(password.to_owned())
+}
+
+fn find_user(store: &dyn ChatStore, name: &str) -> io::Result<models::User> {
+    store
+        .query_user(name)
+        .map_err(other)?
+        .ok_or_else(|| other(format!("no such user: {name}")))
+}
+
+fn find_room(store: &dyn ChatStore, name: &str) -> io::Result<models::Room> {
+    store
+        .query_room(name)
+        .map_err(other)?
+        .ok_or_else(|| other(format!("no such room: {name}")))
+}
+
+/// Record an admin subcommand in the audit log, its actor is
+/// `models::CLI_ACTOR_ID`
+fn audit(
+    store: &dyn ChatStore,
+    action: &str,
+    target: Option<&str>,
+    room: Option<i32>,
+    reason: Option<String>,
+) -> io::Result<()> {
+    let entry = AuditEntry::from_details(models::CLI_ACTOR_ID, action, target.map(str::to_owned), room, reason);
+    store.insert_audit(&entry).map_err(other)?;
+    Ok(())
+}
+
+/// Run an admin subcommand
+pub fn run(action: AdminAction, store: &dyn ChatStore, config: &Config) -> io::Result<()> {
+    match action {
+        AdminAction::Migrate(action) => migrate::command(store, action),
+        AdminAction::User(action) => user(action, store, config),
+        AdminAction::Room(action) => room(action, store),
+        AdminAction::Export { room, output } => export(store, room.as_deref(), output),
+    }
+}
+
+fn user(action: UserAction, store: &dyn ChatStore, config: &Config) -> io::Result<()> {
+    match action {
+        UserAction::Create { name, admin } => {
+            let password = digest(read_password(&name, config)?);
+            match store.insert_user(&name, &password) {
+                Ok(_) => {}
+                Err(QueryError::Conflict) => return Err(other(format!("user {name} already exists"))),
+                Err(err) => return Err(other(err)),
+            }
+            let user = find_user(store, &name)?;
+            let role = if admin { Role::Admin } else { Role::User };
+            if admin {
+                store.update_user_permission(&user.uuid, role.permission_id()).map_err(other)?;
+            }
+            let role = format!("{role:?}").to_lowercase();
+            audit(store, "create_user", Some(&user.uuid), None, Some(role))?;
+            println!("created {name}");
+        }
+        UserAction::List => {
+            for user in store.query_users().map_err(other)? {
+                if user.uuid == models::DELETED_USER_ID {
+                    continue;
+                }
+                let role = if user.permission_id == 1 { "admin" } else { "user" };
+                let state = format!("{:?}", UserState::from_i32(user.state)).to_lowercase();
+                println!("{} {role:<5} {state:<11} {}", user.uuid, user.name);
+            }
+        }
+        UserAction::SetRole { name, role } => {
+            let user = find_user(store, &name)?;
+            store.update_user_permission(&user.uuid, role.permission_id()).map_err(other)?;
+            let role = format!("{role:?}").to_lowercase();
+            audit(store, "set_role", Some(&user.uuid), None, Some(role.clone()))?;
+            println!("{name} is now {role}");
+        }
+        UserAction::ResetPassword { name } => {
+            let user = find_user(store, &name)?;
+            let password = digest(read_password(&name, config)?);
+            store.update_user_password(&user.uuid, &password).map_err(other)?;
+            audit(store, "reset_password", Some(&user.uuid), None, None)?;
+            println!("password of {name} reset");
+        }
+        UserAction::Delete { name } => {
+            let user = find_user(store, &name)?;
+            if store.delete_user(&name).map_err(other)? == 0 {
+                return Err(other(format!("no such user: {name}")));
+            }
+            audit(store, "delete_user", Some(&user.uuid), None, None)?;
+            println!("deleted {name}");
+        }
+    }
+    Ok(())
+}
+
+fn room(action: RoomAction, store: &dyn ChatStore) -> io::Result<()> {
+    match action {
+        RoomAction::List => {
+            for room in store.query_rooms().map_err(other)? {
+                let mut flags = vec![Visibility::from_i32(room.visibility).as_str()];
+                if room.password.is_some() {
+                    flags.push("password");
+                }
+                if room.archived_at.is_some() {
+                    flags.push("archived");
+                }
+                println!("{:>6} {:<24} {}", room.id, room.rname, flags.join(","));
+            }
+        }
+        RoomAction::Create { name, visibility, owner } => {
+            let owner = owner.map(|owner| find_user(store, &owner)).transpose()?;
+            match store.insert_room(&name) {
+                Ok(()) => {}
+                Err(QueryError::Conflict) => return Err(other(format!("room {name} already exists"))),
+                Err(err) => return Err(other(err)),
+            }
+            let room = find_room(store, &name)?;
+            let visibility = Visibility::parse(&visibility).unwrap_or(Visibility::Public);
+            if visibility != Visibility::Public {
+                store.update_room_visibility(room.id, visibility.as_i32()).map_err(other)?;
+            }
+            if let Some(owner) = &owner {
+                store.insert_member(room.id, &owner.uuid, models::ROLE_OWNER).map_err(other)?;
+            }
+            audit(store, "create_room", owner.as_ref().map(|owner| owner.uuid.as_str()), Some(room.id), None)?;
+            println!("created {name}");
+        }
+        RoomAction::Archive { name, undo } => {
+            let room = find_room(store, &name)?;
+            let archived_at = if undo { None } else { Some(chrono::Utc::now().naive_utc()) };
+            store.update_room_archived(room.id, archived_at).map_err(other)?;
+            let action = if undo { "restore_room" } else { "archive_room" };
+            audit(store, action, None, Some(room.id), None)?;
+            println!("{name} {}", if undo { "restored" } else { "archived" });
+        }
+    }
+    Ok(())
+}
+
+/// Users without their password digests, then every room with its messages
+fn export(store: &dyn ChatStore, room: Option<&str>, output: Option<PathBuf>) -> io::Result<()> {
+    let users: Vec<_> = store
+        .query_users()
+        .map_err(other)?
+        .into_iter()
+        .map(|user| {
+            serde_json::json!({
+                "uuid": user.uuid,
+                "name": user.name,
+                "display_name": user.display_name,
+                "permission_id": user.permission_id,
+                "state": user.state,
+                "deleted_at": user.deleted_at,
+            })
+        })
+        .collect();
+    let rooms = match room {
+        Some(name) => vec![find_room(store, name)?],
+        None => store.query_rooms().map_err(other)?,
+    };
+    let mut exported = Vec::with_capacity(rooms.len());
+    for room in rooms {
+        let messages = store.query_message(room.id).map_err(other)?;
+        exported.push(serde_json::json!({
+            "id": room.id,
+            "name": room.rname,
+            "visibility": Visibility::from_i32(room.visibility).as_str(),
+            "archived_at": room.archived_at,
+            "messages": messages,
+        }));
+    }
+    let export = serde_json::json!({
+        "exported_at": chrono::Utc::now().naive_utc(),
+        "users": users,
+        "rooms": exported,
+    });
+
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = io::BufWriter::new(writer);
+    serde_json::to_writer_pretty(&mut writer, &export).map_err(other)?;
+    writeln!(writer)?;
+    writer.flush()
+}
@@ -0,0 +1,714 @@
+//! Chat commands of a websocket session. They run on the blocking thread
+//! pool so Diesel calls don't stall the worker serving the websockets, the
+//! session applies the outcome once they are done.
+
+use std::collections::{HashMap, HashSet};
+
+use actix::Addr;
+use actix_web::web;
+use sha256::digest;
+
+use crate::config::Config;
+use crate::events::SystemEvent;
+use crate::models::{self, AuditEntry, Profile, Room, RoomBan, RoomMute, User, UserState, Visibility};
+use crate::profile;
+use crate::store::{ChatStore, QueryError};
+use crate::server;
+use crate::throttle::Throttle;
+
+/// What the session has to do once a command is done
+pub enum Reply {
+    /// Send a text frame to the client
+    Text(String),
+    /// Ask the chat server for the rooms the user may see and send them
+    ListRooms(HashSet<String>),
+    /// Tell the chat server the session moved to another room, its
+    /// messages go to the shard of that room afterwards
+    Join { name: String, hidden: bool },
+    /// Issue an event on the event bus
+    Event(SystemEvent),
+    /// Close the websocket
+    Close,
+}
+
+/// Snapshot of a session a command runs against
+pub struct Command {
+    pub id: String,
+    pub room: String,
+    pub addr: Addr<server::ChatServer>,
+    /// Shard of `room`, see `server::RoomShard`
+    pub shard: Option<Addr<server::RoomShard>>,
+    pub store: web::Data<dyn ChatStore>,
+    pub join_throttle: web::Data<Throttle>,
+    pub config: web::Data<Config>,
+    /// replies in the order they have to be applied
+    pub replies: Vec<Reply>,
+}
+
+impl Command {
+    fn text<T: Into<String>>(&mut self, text: T) {
+        self.replies.push(Reply::Text(text.into()));
+    }
+
+    /// Make sure the default room exists in the database
+    pub fn ensure_default_room(&self) -> Result<(), QueryError> {
+        if let Some(db_room) = self.store.query_room(&self.config.default_room)? {
+            log::info!("{} exit", db_room.rname);
+        } else {
+            match self.store.insert_room(&self.config.default_room) {
+                // another session created it first
+                Ok(()) | Err(QueryError::Conflict) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Room the session is currently in
+    fn current_room(&self) -> Result<Option<Room>, QueryError> {
+        self.store.query_room(&self.room)
+    }
+
+    /// Global admins moderate every room, otherwise a moderator role is needed
+    fn is_moderator(&self, room: &Room) -> Result<bool, QueryError> {
+        if let Some(user) = self.store.query_user_from_id(&self.id)? {
+            if user.permission_id == 1 {
+                return Ok(true);
+            }
+        }
+        Ok(self.store.query_member(room.id, &self.id)?
+            .is_some_and(|member| member.role >= models::ROLE_MODERATOR))
+    }
+
+    /// Whether the session may kick, ban or mute `target` in `room`.
+    /// Global admins may act on anyone, room moderators only on users with
+    /// a lower room role than their own.
+    fn can_moderate(&self, room: &Room, target: &User) -> Result<bool, QueryError> {
+        if target.uuid == self.id {
+            return Ok(false);
+        }
+        if let Some(user) = self.store.query_user_from_id(&self.id)? {
+            if user.permission_id == 1 {
+                return Ok(true);
+            }
+        }
+        let role = |id: &str| -> Result<Option<i32>, QueryError> {
+            Ok(self.store.query_member(room.id, id)?.map(|member| member.role))
+        };
+        Ok(match (role(&self.id)?, role(&target.uuid)?) {
+            (Some(own), Some(other)) => own >= models::ROLE_MODERATOR && own > other,
+            (Some(own), None) => own >= models::ROLE_MODERATOR,
+            _ => false,
+        })
+    }
+
+    /// Resolve the current room and the target user of a moderation
+    /// command, telling the client why when the command is not allowed
+    fn moderation_target(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<(Room, User)>, QueryError> {
+        let Some(room) = self.current_room()? else {
+            self.text("!!! room does not exist");
+            return Ok(None);
+        };
+        let Some(target) = self.store.query_user(name)? else {
+            self.text(format!("!!! no such user: {name}"));
+            return Ok(None);
+        };
+        if !self.can_moderate(&room, &target)? {
+            self.text(format!("!!! you can't moderate {name} in this room"));
+            return Ok(None);
+        }
+        Ok(Some((room, target)))
+    }
+
+    /// Record a moderation or admin action done by the session user
+    fn audit(&self, action: &str, target: Option<&str>, room: Option<i32>, reason: Option<String>) -> Result<(), QueryError> {
+        let entry = AuditEntry::from_details(&self.id, action, target.map(str::to_owned), room, reason);
+        self.store.insert_audit(&entry)?;
+        Ok(())
+    }
+
+    /// Send a message to the others in the room
+    fn send_to_room(&self, msg: String) {
+        if let Some(shard) = &self.shard {
+            shard.do_send(server::ClientMessage {
+                id: self.id.clone(),
+                msg,
+                room: self.room.clone(),
+            });
+        }
+    }
+
+    /// Tell the whole room, the session included, about a moderation action
+    fn announce(&mut self, msg: String) {
+        self.text(msg.clone());
+        self.send_to_room(msg);
+    }
+
+    /// Mute the session user in the current room for flooding it
+    pub fn flood_mute(&mut self, duration: chrono::Duration) -> Result<(), QueryError> {
+        let Some(room) = self.current_room()? else {
+            return Ok(());
+        };
+        // moderators are only slowed down
+        if self.is_moderator(&room)? {
+            return Ok(());
+        }
+        let mute = RoomMute::from_details(room.id, &self.id, &self.id, Some(duration));
+        self.store.insert_mute(&mute)?;
+        self.audit("mute", Some(&self.id), Some(room.id), Some("flooding".to_owned()))?;
+        let name = self.store.query_user_from_id(&self.id)?.map_or_else(|| self.id.clone(), |user| user.name);
+        match mute.expires {
+            Some(expires) => self.announce(format!("{name} was muted until {expires} for flooding")),
+            None => self.announce(format!("{name} was muted for flooding")),
+        }
+        Ok(())
+    }
+
+    /// Set the display name of the session user, `None` goes back to the
+    /// login name. Names may not be taken as login or display name by
+    /// anyone else.
+    fn set_display_name(&mut self, name: Option<&str>) -> Result<(), QueryError> {
+        let Some(name) = name else {
+            self.store.update_user_display_name(&self.id, None)?;
+            self.text("display name cleared");
+            return self.profile_changed();
+        };
+        if !models::valid_display_name(name) {
+            self.text(format!(
+                "!!! display names are 2 to {} ASCII letters, digits, _, - or ., words separated by single spaces",
+                models::DISPLAY_NAME_MAX
+            ));
+            return Ok(());
+        }
+        if profile::display_name_taken(&**self.store, &self.id, name)? {
+            self.text(format!("!!! {name} is taken"));
+            return Ok(());
+        }
+        match self.store.update_user_display_name(&self.id, Some(name)) {
+            Ok(_) => {
+                self.text(format!("you are now shown as {name}"));
+                self.profile_changed()
+            }
+            // taken by another session in the meantime
+            Err(QueryError::Conflict) => {
+                self.text(format!("!!! {name} is taken"));
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Tell the rooms the user is in how they are shown now
+    fn profile_changed(&mut self) -> Result<(), QueryError> {
+        if let Some(user) = self.store.query_user_from_id(&self.id)? {
+            let profile = self
+                .store
+                .query_profile(&self.id)?
+                .unwrap_or_else(|| Profile::from_details(&self.id));
+            self.replies.push(Reply::Event(profile::changed(&user, &profile)));
+        }
+        Ok(())
+    }
+
+    /// Move the session into a room it is allowed to be in
+    fn enter_room(&mut self, room: &Room) {
+        self.room = room.rname.clone();
+        self.replies.push(Reply::Join {
+            name: self.room.clone(),
+            hidden: Visibility::from_i32(room.visibility) == Visibility::Private,
+        });
+
+        self.text("joined");
+    }
+
+    /// Join a room, creating it with the session user as owner if it does
+    /// not exist. A password given when creating the room protects it.
+    ///
+    /// Non members must supply the password of a protected room, joining
+    /// any other room that is not public files a join request for the room
+    /// moderators instead.
+    fn join_room(&mut self, name: &str, password: Option<&str>) -> Result<(), QueryError> {
+        let room = if let Some(db_room) = self.store.query_room(name)? {
+            log::info!("{} exist", db_room.rname);
+            db_room
+        } else {
+            let created = match self.store.insert_room(name) {
+                Ok(()) => true,
+                // lost the race against another session creating the room,
+                // join it like any existing one
+                Err(QueryError::Conflict) => false,
+                Err(err) => return Err(err),
+            };
+            let mut db_room = self.store.query_room(name)?
+                .ok_or_else(|| QueryError::from(diesel::result::Error::NotFound))?;
+            if created {
+                self.store.insert_member(db_room.id, &self.id, models::ROLE_OWNER)?;
+                if let Some(password) = password {
+                    db_room.password = Some(digest(password));
+                    self.store.update_room_password(db_room.id, db_room.password.clone())?;
+                }
+            }
+            db_room
+        };
+
+        if room.archived_at.is_some() {
+            self.text(format!("!!! {name} is archived"));
+            return Ok(());
+        }
+
+        if let Some(ban) = self.store.query_ban(room.id, &self.id)? {
+            let until = ban.expires.map_or("forever".to_owned(), |expires| format!("until {expires}"));
+            let reason = ban.reason.unwrap_or_default();
+            self.text(format!("!!! you are banned from {name} {until} {reason}"));
+            return Ok(());
+        }
+
+        if self.store.query_member(room.id, &self.id)?.is_none() {
+            if let Some(ref hash) = room.password {
+                let key = format!("{}/{}", self.id, room.id);
+                if let Some(left) = self.join_throttle.locked(&key) {
+                    self.text(format!("!!! too many wrong passwords, retry in {}s", left.as_secs() + 1));
+                    return Ok(());
+                }
+                match password {
+                    Some(password) if &digest(password) == hash => {
+                        self.join_throttle.reset(&key);
+                    }
+                    Some(_) => {
+                        self.join_throttle.fail(&key);
+                        self.text("!!! wrong room password");
+                        return Ok(());
+                    }
+                    None => {
+                        self.text(format!("!!! room {name} requires a password"));
+                        return Ok(());
+                    }
+                }
+                self.store.insert_member(room.id, &self.id, models::ROLE_MEMBER)?;
+            } else if Visibility::from_i32(room.visibility) == Visibility::Public {
+                self.store.insert_member(room.id, &self.id, models::ROLE_MEMBER)?;
+            } else {
+                self.store.insert_request(room.id, &self.id)?;
+                self.text(format!("room {name} is not public, join request sent"));
+                return Ok(());
+            }
+        }
+        self.enter_room(&room);
+        Ok(())
+    }
+
+    /// Handle a text frame, either a `/command` or a chat message
+    pub fn handle_text(&mut self, m: &str) -> Result<(), QueryError> {
+        // we check for /sss type of messages
+        if m.starts_with('/') {
+            let v: Vec<&str> = m.splitn(2, ' ').collect();
+            match v[0] {
+                "/list" => {
+                    // the chat server only knows which rooms are open, the
+                    // session adds which private ones the user may see
+                    println!("List rooms");
+                    let member_of = self.store.query_member_rooms(&self.id)?
+                        .into_iter()
+                        .collect();
+                    self.replies.push(Reply::ListRooms(member_of));
+                }
+                "/join" => {
+                    if v.len() == 2 {
+                        let mut args = v[1].split_whitespace();
+                        let name = args.next().unwrap_or_default();
+                        self.join_room(name, args.next())?;
+                    } else {
+                        self.text("!!! room name is required");
+                    }
+                }
+                "/name" => self.set_display_name(v.get(1).copied())?,
+                "/history" => {
+                    if let Some(now_room) = self.store.query_room(&self.room)? {
+                        // names come from the users table, they may have
+                        // changed since the message was sent. Each sender
+                        // is looked up once, those gone show as deleted.
+                        let mut names: HashMap<String, String> = HashMap::new();
+                        for i in self.store.query_message(now_room.id)? {
+                            if !names.contains_key(&i.sender_id) {
+                                let name = self
+                                    .store
+                                    .query_user_from_id(&i.sender_id)?
+                                    .map_or(models::DELETED_USER_NAME.to_owned(), |user| user.shown_name().to_owned());
+                                names.insert(i.sender_id.clone(), name);
+                            }
+                            self.text(message_event(&self.room, &i.sender_id, &names[&i.sender_id], &i.content));
+                        }
+                    }
+                }
+                "/rm" => {
+                    if v.len() == 2 {
+                        if let Some(src_peo) = self.store.query_user_from_id(&self.id)? {
+                            if src_peo.permission_id == 1 {
+                                let target = self.store.query_user(v[1])?;
+                                self.store.delete_user(v[1])?;
+                                if let Some(target) = target {
+                                    self.audit("delete_user", Some(&target.uuid), None, None)?;
+                                    self.text(format!("deleted {}", target.name));
+                                    self.replies.push(Reply::Event(SystemEvent::UserDeleted { id: target.uuid }));
+                                }
+                            }
+                        }
+                    } else {
+                        self.text("!!! name is required");
+                    }
+
+                }
+                "/suspend" | "/unsuspend" | "/erase" => {
+                    if v.len() == 2 {
+                        let admin = self.store.query_user_from_id(&self.id)?
+                            .is_some_and(|user| user.permission_id == 1);
+                        let target = self.store.query_user(v[1])?;
+                        match target {
+                            _ if !admin => self.text("!!! root permission is required"),
+                            None => self.text(format!("!!! no such user: {}", v[1])),
+                            Some(target) => {
+                                let changed = match v[0] {
+                                    "/suspend" => self.store.update_user_state(&target.uuid, UserState::Suspended),
+                                    "/unsuspend" => self.store.update_user_state(&target.uuid, UserState::Active),
+                                    _ => self.store.erase_user(&target.uuid),
+                                }?;
+                                if changed == 0 && v[0] == "/erase" {
+                                    self.text(format!("!!! {} must be deleted before it can be erased", target.name));
+                                } else if changed == 0 {
+                                    self.text(format!("!!! {} is deleted", target.name));
+                                } else {
+                                    self.audit(&v[0][1..], Some(&target.uuid), None, None)?;
+                                    self.text(format!("{} done for {}", &v[0][1..], target.name));
+                                    if v[0] == "/suspend" {
+                                        self.replies.push(Reply::Event(SystemEvent::UserSuspended { id: target.uuid }));
+                                    }
+                                }
+                            }
+                        }
+                    } else {
+                        self.text("!!! name is required");
+                    }
+                }
+                "/deactivate" => {
+                    self.store.update_user_state(&self.id, UserState::Deactivated)?;
+                    self.text("account deactivated, log in again to reactivate it");
+                    self.replies.push(Reply::Close);
+                }
+                "/visibility" => {
+                    let visibility = if v.len() == 2 { Visibility::parse(v[1]) } else { None };
+                    match (visibility, self.current_room()?) {
+                        (None, _) => self.text("!!! visibility must be public, private or invite"),
+                        (Some(visibility), Some(room)) if self.is_moderator(&room)? => {
+                            self.store.update_room_visibility(room.id, visibility.as_i32())?;
+                            self.audit("set_visibility", None, Some(room.id), Some(v[1].to_owned()))?;
+                            self.addr.do_send(server::SetHidden {
+                                name: room.rname,
+                                hidden: visibility == Visibility::Private,
+                            });
+                            self.text(format!("visibility set to {}", v[1]));
+                        }
+                        _ => self.text("!!! only room moderators can change visibility"),
+                    }
+                }
+                "/invite" => {
+                    if v.len() == 2 {
+                        let room = self.current_room()?;
+                        let invitee = self.store.query_user(v[1])?;
+                        match (room, invitee) {
+                            (Some(room), Some(invitee)) => {
+                                if self.store.query_member(room.id, &self.id)?.is_none() {
+                                    self.text("!!! only room members can invite");
+                                } else if self.store.query_member(room.id, &invitee.uuid)?.is_some() {
+                                    self.text(format!("!!! {} is already a member", invitee.name));
+                                } else {
+                                    self.store.insert_invite(room.id, &invitee.uuid, &self.id)?;
+                                    self.text(format!("invited {}", invitee.name));
+                                }
+                            }
+                            (_, None) => self.text(format!("!!! no such user: {}", v[1])),
+                            _ => self.text("!!! room does not exist"),
+                        }
+                    } else {
+                        self.text("!!! user name is required");
+                    }
+                }
+                "/invites" => {
+                    for (_, room) in self.store.query_invites(&self.id)? {
+                        self.text(room.rname);
+                    }
+                }
+                "/accept" | "/decline" => {
+                    if v.len() == 2 {
+                        let invite = self.store.query_invites(&self.id)?
+                            .into_iter()
+                            .find(|(_, room)| room.rname == v[1]);
+                        if let Some((invite, room)) = invite {
+                            self.store.delete_invite(invite.room_id, &self.id)?;
+                            if v[0] == "/accept" {
+                                self.store.delete_request(room.id, &self.id)?;
+                                self.store.insert_member(room.id, &self.id, models::ROLE_MEMBER)?;
+                                self.enter_room(&room);
+                            } else {
+                                self.text(format!("declined invite to {}", room.rname));
+                            }
+                        } else {
+                            self.text(format!("!!! no invite to {}", v[1]));
+                        }
+                    } else {
+                        self.text("!!! room name is required");
+                    }
+                }
+                "/requests" => match self.current_room()? {
+                    Some(room) if self.is_moderator(&room)? => {
+                        for (_, user) in self.store.query_requests(room.id)? {
+                            self.text(user.name);
+                        }
+                    }
+                    _ => self.text("!!! only room moderators can see join requests"),
+                },
+                "/approve" | "/deny" => {
+                    if v.len() == 2 {
+                        let room = match self.current_room()? {
+                            Some(room) if self.is_moderator(&room)? => Some(room),
+                            _ => None,
+                        };
+                        if let Some(room) = room {
+                            let request = self.store.query_requests(room.id)?
+                                .into_iter()
+                                .find(|(_, user)| user.name == v[1]);
+                            if let Some((request, user)) = request {
+                                self.store.delete_request(room.id, &request.user_id)?;
+                                if v[0] == "/approve" {
+                                    self.store.insert_member(room.id, &user.uuid, models::ROLE_MEMBER)?;
+                                    self.audit("approve_request", Some(&user.uuid), Some(room.id), None)?;
+                                    self.text(format!("approved {}", user.name));
+                                } else {
+                                    self.audit("deny_request", Some(&user.uuid), Some(room.id), None)?;
+                                    self.text(format!("denied {}", user.name));
+                                }
+                            } else {
+                                self.text(format!("!!! no join request from {}", v[1]));
+                            }
+                        } else {
+                            self.text("!!! only room moderators can handle join requests");
+                        }
+                    } else {
+                        self.text("!!! user name is required");
+                    }
+                }
+                "/password" => match self.current_room()? {
+                    Some(room) => {
+                        let is_owner = self.store.query_member(room.id, &self.id)?
+                            .is_some_and(|member| member.role == models::ROLE_OWNER);
+                        // split like `/join` does, or the room couldn't be
+                        // joined with it
+                        let mut args = v.get(1).copied().unwrap_or_default().split_whitespace();
+                        let password = args.next();
+                        if !is_owner {
+                            self.text("!!! only the room owner can change the password");
+                        } else if args.next().is_some() {
+                            self.text("!!! room passwords can't contain spaces");
+                        } else {
+                            let password = password.map(digest);
+                            let removed = password.is_none();
+                            self.store.update_room_password(room.id, password)?;
+                            let action = if removed { "remove_room_password" } else { "set_room_password" };
+                            self.audit(action, None, Some(room.id), None)?;
+                            self.text(if removed { "room password removed" } else { "room password changed" });
+                        }
+                    }
+                    None => self.text("!!! room does not exist"),
+                },
+                "/kick" | "/ban" => {
+                    if v.len() == 2 {
+                        let mut args = v[1].splitn(2, ' ');
+                        let name = args.next().unwrap_or_default();
+                        let mut rest = args.next().map(str::trim).unwrap_or_default();
+                        let mut duration = None;
+                        if v[0] == "/ban" {
+                            let mut args = rest.splitn(2, ' ');
+                            duration = args.next().and_then(parse_duration);
+                            if duration.is_some() {
+                                rest = args.next().map(str::trim).unwrap_or_default();
+                            }
+                        }
+                        let reason = if rest.is_empty() { None } else { Some(rest.to_owned()) };
+                        if let Some((room, target)) = self.moderation_target(name)? {
+                            self.store.delete_member(room.id, &target.uuid)?;
+                            let action = if v[0] == "/ban" {
+                                let ban = RoomBan::from_details(room.id, &target.uuid, &self.id, reason.clone(), duration);
+                                self.store.insert_ban(&ban)?;
+                                match ban.expires {
+                                    Some(expires) => format!("banned until {expires}"),
+                                    None => "banned".to_owned(),
+                                }
+                            } else {
+                                "kicked".to_owned()
+                            };
+                            self.audit(&v[0][1..], Some(&target.uuid), Some(room.id), reason.clone())?;
+                            let reason = reason.unwrap_or_else(|| "no reason given".to_owned());
+                            self.addr.do_send(server::Kick {
+                                id: target.uuid,
+                                room: room.rname,
+                                reason: format!("{action}, {reason}"),
+                            });
+                            self.announce(format!("{} was {action}: {reason}", target.name));
+                        }
+                    } else {
+                        self.text("!!! user name is required");
+                    }
+                }
+                "/mute" => {
+                    if v.len() == 2 {
+                        let mut args = v[1].splitn(2, ' ');
+                        let name = args.next().unwrap_or_default();
+                        let mut rest = args.next().map(str::trim).unwrap_or_default();
+                        let mut args = rest.splitn(2, ' ');
+                        let duration = args.next().and_then(parse_duration);
+                        if duration.is_some() {
+                            rest = args.next().map(str::trim).unwrap_or_default();
+                        }
+                        let reason = if rest.is_empty() { None } else { Some(rest.to_owned()) };
+                        if let Some((room, target)) = self.moderation_target(name)? {
+                            let mute = RoomMute::from_details(room.id, &target.uuid, &self.id, duration);
+                            self.store.insert_mute(&mute)?;
+                            self.audit("mute", Some(&target.uuid), Some(room.id), reason.clone())?;
+                            let action = match mute.expires {
+                                Some(expires) => format!("muted until {expires}"),
+                                None => "muted".to_owned(),
+                            };
+                            let reason = reason.unwrap_or_else(|| "no reason given".to_owned());
+                            self.announce(format!("{} was {action}: {reason}", target.name));
+                        }
+                    } else {
+                        self.text("!!! user name is required");
+                    }
+                }
+                "/unban" | "/unmute" => {
+                    if v.len() == 2 {
+                        if let Some((room, target)) = self.moderation_target(v[1])? {
+                            let (deleted, state) = if v[0] == "/unban" {
+                                (self.store.delete_ban(room.id, &target.uuid)?, "banned")
+                            } else {
+                                (self.store.delete_mute(room.id, &target.uuid)?, "muted")
+                            };
+                            // nothing lifted, nothing to record
+                            if deleted == 0 {
+                                self.text(format!("!!! {} is not {state}", target.name));
+                            } else {
+                                self.audit(&v[0][1..], Some(&target.uuid), Some(room.id), None)?;
+                                self.announce(format!("{} was un{state}", target.name));
+                            }
+                        }
+                    } else {
+                        self.text("!!! user name is required");
+                    }
+                }
+                "/mod" => {
+                    if v.len() == 2 {
+                        let room = self.current_room()?;
+                        let user = self.store.query_user(v[1])?;
+                        match (room, user) {
+                            (Some(room), Some(user)) => {
+                                let is_owner = self.store.query_member(room.id, &self.id)?
+                                    .is_some_and(|member| member.role == models::ROLE_OWNER);
+                                if !is_owner {
+                                    self.text("!!! only the room owner can appoint moderators");
+                                } else if self.store.query_member(room.id, &user.uuid)?.is_none() {
+                                    self.text(format!("!!! {} is not a member", user.name));
+                                } else {
+                                    self.store.insert_member(room.id, &user.uuid, models::ROLE_MODERATOR)?;
+                                    self.audit("appoint_moderator", Some(&user.uuid), Some(room.id), None)?;
+                                    self.text(format!("{} is now a moderator", user.name));
+                                    self.replies.push(Reply::Event(SystemEvent::RoleChanged {
+                                        id: user.uuid,
+                                        room: room.rname,
+                                        role: models::ROLE_MODERATOR,
+                                    }));
+                                }
+                            }
+                            (_, None) => self.text(format!("!!! no such user: {}", v[1])),
+                            _ => self.text("!!! room does not exist"),
+                        }
+                    } else {
+                        self.text("!!! user name is required");
+                    }
+                }
+                _ => self.text(format!("!!! unknown command: {m:?}")),
+            }
+        } else {
+            if let Some(now_room) = self.store.query_room(&self.room)? {
+                if now_room.archived_at.is_some() {
+                    self.text("!!! this room is archived");
+                    return Ok(());
+                }
+                if let Some(mute) = self.store.query_mute(now_room.id, &self.id)? {
+                    match mute.expires {
+                        Some(expires) => self.text(format!("!!! you are muted in this room until {expires}")),
+                        None => self.text("!!! you are muted in this room"),
+                    }
+                    return Ok(());
+                }
+                self.store.insert_message(m, now_room.id, &self.id)?;
+            }
+            // the sender is attached here, the content stays as typed
+            let Some(sender) = self.store.query_user_from_id(&self.id)? else {
+                return Ok(());
+            };
+            // send message to the shard of the room
+            self.send_to_room(message_event(&self.room, &sender.uuid, sender.shown_name(), m));
+        }
+        Ok(())
+    }
+}
+
+/// Chat message frame, the sender is the one the server knows
+fn message_event(room: &str, sender_id: &str, sender_name: &str, content: &str) -> String {
+    serde_json::json!({
+        "type": "message",
+        "room": room,
+        "sender": {
+            "id": sender_id,
+            "name": sender_name,
+        },
+        "content": content,
+    })
+    .to_string()
+}
+
+/// Parse durations like `30s`, `10m`, `2h` or `7d`
+fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    let unit = value.chars().last()?;
+    let amount = value[..value.len() - unit.len_utf8()].parse::<u32>().ok()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(chrono::Duration::seconds(i64::from(amount) * seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("45s"), Some(chrono::Duration::seconds(45)));
+        assert_eq!(parse_duration("30m"), Some(chrono::Duration::minutes(30)));
+        assert_eq!(parse_duration("2h"), Some(chrono::Duration::hours(2)));
+        assert_eq!(parse_duration("7d"), Some(chrono::Duration::days(7)));
+    }
+
+    #[test]
+    fn parse_duration_refuses_others() {
+        for value in ["", "m", "10", "10w", "-5m", "1.5h", "spam", "5é"] {
+            assert_eq!(parse_duration(value), None, "{value:?}");
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! Server configuration. Defaults are overridden by the TOML file, then by
+//! environment variables, then by command line flags. The result is checked
+//! once at startup and shared with the app as `web::Data<Config>`.
+
+use std::{
+    io,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use derive_more::Display;
+use serde::Deserialize;
+
+/// File read when `--config` and `CHAT_CONFIG` are not given, it is fine
+/// for it to be missing
+const DEFAULT_CONFIG_FILE: &str = "verdant_chat.toml";
+
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    #[display(fmt = "can't read {}: {_1}", "_0.display()")]
+    Read(PathBuf, io::Error),
+    #[display(fmt = "invalid config file {}: {_1}", "_0.display()")]
+    Parse(PathBuf, toml::de::Error),
+    #[display(fmt = "invalid value for {_0}: {_1:?}")]
+    Env(&'static str, String),
+    #[display(fmt = "invalid config: {_0}")]
+    Invalid(String),
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(err: ConfigError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `host:port` the HTTP server listens on
+    pub bind: String,
+    /// Number of HTTP worker threads
+    pub workers: usize,
+    /// Store to open, see `store::connect`
+    pub database_url: String,
+    /// Apply pending migrations at startup instead of refusing to start
+    pub auto_migrate: bool,
+    /// Seconds between heartbeat pings sent to the clients
+    pub heartbeat_interval: u64,
+    /// Seconds without a client response before its session is dropped
+    pub client_timeout: u64,
+    /// Directory holding the pages and the `/static` files
+    pub static_dir: PathBuf,
+    /// Room every session starts in and returns to when kicked
+    pub default_room: String,
+    /// Directory the resized avatars are kept in, served under `/avatars`,
+    /// created when missing
+    pub avatar_dir: PathBuf,
+    /// Width and height of stored avatars, in pixels
+    pub avatar_size: u32,
+    /// Largest avatar upload accepted, in bytes
+    pub max_avatar_upload: usize,
+    /// Shortest password accepted, in characters
+    pub min_password_length: usize,
+    /// Seconds a password reset token stays valid
+    pub reset_token_ttl: u64,
+    /// Admins have to set up two factor authentication before they can chat
+    pub require_2fa_admins: bool,
+    /// Room moderators and owners have to as well
+    pub require_2fa_moderators: bool,
+    /// Issuer authenticator apps list the account under
+    pub totp_issuer: String,
+    /// Where login sessions are kept, `database` or `memory`. Sessions in
+    /// memory are lost on restart and only known to this instance.
+    pub session_store: String,
+    /// Seconds a login session lasts at most
+    pub session_lifetime: u64,
+    /// Seconds a login session may go unused before it ends
+    pub session_idle_timeout: u64,
+    /// Key of at least 64 bytes the session cookies are encrypted with.
+    /// Without one a random key is used and every session ends on restart.
+    pub cookie_secret: Option<String>,
+    /// Largest websocket frame accepted, in bytes
+    pub max_frame_size: usize,
+    /// Longest chat message or command accepted, in characters
+    pub max_message_length: usize,
+    /// Number of room shards, rooms are spread over them by name and each
+    /// delivers on a thread of its own
+    pub shards: usize,
+    /// Seconds sessions get to close on shutdown before workers are stopped
+    pub shutdown_timeout: u64,
+    /// Seconds clients are told to wait before reconnecting on shutdown
+    pub reconnect_delay: u64,
+    /// Broker connecting the instances, see `broker::connect`. Without one
+    /// sessions only reach sessions of the same instance.
+    pub broker_url: Option<String>,
+    /// Pub/sub channel the instances share on the broker
+    pub broker_channel: String,
+    /// Serve HTTPS, `bind` then only redirects if `redirect_http` is set
+    pub tls: Option<TlsConfig>,
+    /// Rate limits of chat messages, commands and logins
+    pub limits: Limits,
+}
+
+/// Token bucket, refilled by `rate` tokens a second up to `burst` tokens
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl Limit {
+    const fn new(rate: f64, burst: f64) -> Limit {
+        Limit { rate, burst }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Chat messages of one websocket session
+    pub session_messages: Limit,
+    /// Chat messages of one user, all their sessions together
+    pub user_messages: Limit,
+    /// Chat messages into one room, from everyone together
+    pub room_messages: Limit,
+    /// Slash commands of one websocket session
+    pub commands: Limit,
+    /// Login and registration attempts from one client address
+    pub login_ip: Limit,
+    /// Login and registration attempts for one user name
+    pub login_user: Limit,
+    /// Slow down errors in a row before a flooding user is muted in the room
+    pub flood_strikes: u32,
+    /// Seconds a flooding user is muted for, 0 only slows them down
+    pub flood_mute: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            session_messages: Limit::new(2.0, 10.0),
+            user_messages: Limit::new(3.0, 15.0),
+            room_messages: Limit::new(50.0, 200.0),
+            commands: Limit::new(1.0, 10.0),
+            login_ip: Limit::new(0.2, 10.0),
+            login_user: Limit::new(0.1, 5.0),
+            flood_strikes: 5,
+            flood_mute: 60,
+        }
+    }
+}
+
+impl Limits {
+    pub fn flood_mute(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.flood_mute as i64)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// `host:port` the HTTPS server listens on
+    pub bind: String,
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// Listen on `bind` as well, redirecting everything to HTTPS
+    pub redirect_http: bool,
+    /// Seconds between checks whether the certificate files changed
+    pub reload_interval: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            bind: "127.0.0.1:8443".to_owned(),
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            redirect_http: false,
+            reload_interval: 60,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval)
+    }
+
+    /// Port of the HTTPS listener, where plain requests get redirected to
+    pub fn port(&self) -> u16 {
+        self.bind
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .map_or(443, |addr| addr.port())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1:8080".to_owned(),
+            workers: 2,
+            database_url: String::new(),
+            auto_migrate: true,
+            heartbeat_interval: 5,
+            client_timeout: 10,
+            static_dir: PathBuf::from("./static"),
+            default_room: "main".to_owned(),
+            avatar_dir: PathBuf::from("./avatars"),
+            avatar_size: 128,
+            max_avatar_upload: 2_097_152,
+            min_password_length: 10,
+            reset_token_ttl: 86_400,
+            require_2fa_admins: false,
+            require_2fa_moderators: false,
+            totp_issuer: "verdant_chat".to_owned(),
+            session_store: "database".to_owned(),
+            session_lifetime: 2_592_000,
+            session_idle_timeout: 604_800,
+            cookie_secret: None,
+            max_frame_size: 65_536,
+            max_message_length: 2_000,
+            shards: 4,
+            shutdown_timeout: 10,
+            reconnect_delay: 5,
+            broker_url: None,
+            broker_channel: "verdant_chat".to_owned(),
+            tls: None,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Command line flags overriding the configuration
+#[derive(Debug, Default, clap::Args)]
+pub struct Overrides {
+    /// Config file, defaults to verdant_chat.toml when it exists
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, host:port
+    #[arg(long, global = true)]
+    pub bind: Option<String>,
+    /// Number of HTTP workers
+    #[arg(long, global = true)]
+    pub workers: Option<usize>,
+    /// mysql://, postgres://, sqlite:// or memory:// url of the store
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    /// Apply pending migrations at startup, true or false
+    #[arg(long, global = true)]
+    pub auto_migrate: Option<bool>,
+    /// Directory of the static files
+    #[arg(long, global = true)]
+    pub static_dir: Option<PathBuf>,
+    /// Room sessions start in
+    #[arg(long, global = true)]
+    pub default_room: Option<String>,
+    /// Directory of the uploaded avatars
+    #[arg(long, global = true)]
+    pub avatar_dir: Option<PathBuf>,
+    /// Number of room shards
+    #[arg(long, global = true)]
+    pub shards: Option<usize>,
+    /// redis:// url of the broker shared with the other instances
+    #[arg(long, global = true)]
+    pub broker_url: Option<String>,
+    /// PEM certificate chain, enables HTTPS
+    #[arg(long, global = true)]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key, enables HTTPS
+    #[arg(long, global = true)]
+    pub tls_key: Option<PathBuf>,
+    /// Address of the HTTPS listener, host:port
+    #[arg(long, global = true)]
+    pub tls_bind: Option<String>,
+    /// Redirect plain HTTP on --bind to HTTPS
+    #[arg(long, global = true)]
+    pub redirect_http: Option<bool>,
+}
+
+/// Looks up an environment variable, `None` if it isn't set
+type EnvLookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Parse the environment variable `name` if it is set
+fn env<T: std::str::FromStr>(lookup: EnvLookup, name: &'static str) -> Result<Option<T>, ConfigError> {
+    match lookup(name) {
+        Some(value) => value.parse().map(Some).map_err(|_| ConfigError::Env(name, value)),
+        None => Ok(None),
+    }
+}
+
+/// `true`/`false` like the config file, `1`/`0` as well for the env
+fn env_bool(lookup: EnvLookup, name: &'static str) -> Result<Option<bool>, ConfigError> {
+    match lookup(name).as_deref() {
+        Some("true" | "1") => Ok(Some(true)),
+        Some("false" | "0") => Ok(Some(false)),
+        Some(value) => Err(ConfigError::Env(name, value.to_owned())),
+        None => Ok(None),
+    }
+}
+
+impl Config {
+    /// Build the configuration from every layer and validate it. Files only
+    /// the server reads are checked when `serving`, the admin subcommands
+    /// don't need them.
+    pub fn load(overrides: &Overrides, serving: bool) -> Result<Config, ConfigError> {
+        Config::load_with(overrides, serving, &|name| std::env::var(name).ok())
+    }
+
+    /// `load` with the environment variables looked up by `lookup`
+    fn load_with(overrides: &Overrides, serving: bool, lookup: EnvLookup) -> Result<Config, ConfigError> {
+        let path = match overrides.config.clone() {
+            Some(path) => Some(path),
+            None => env::<PathBuf>(lookup, "CHAT_CONFIG")?,
+        };
+        let mut config = match path {
+            Some(path) => Config::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+        config.apply_env(lookup)?;
+        config.apply_overrides(overrides);
+        config.validate(serving)?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_owned(), err))?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse(path.to_owned(), err))
+    }
+
+    fn apply_env(&mut self, lookup: EnvLookup) -> Result<(), ConfigError> {
+        // DATABASE_URL and AUTO_MIGRATE keep their names from before the
+        // config file existed, diesel's CLI reads the former too
+        if let Some(database_url) = env(lookup, "DATABASE_URL")? {
+            self.database_url = database_url;
+        }
+        if let Some(auto_migrate) = env_bool(lookup, "AUTO_MIGRATE")? {
+            self.auto_migrate = auto_migrate;
+        }
+        if let Some(bind) = env(lookup, "CHAT_BIND")? {
+            self.bind = bind;
+        }
+        if let Some(workers) = env(lookup, "CHAT_WORKERS")? {
+            self.workers = workers;
+        }
+        if let Some(heartbeat_interval) = env(lookup, "CHAT_HEARTBEAT_INTERVAL")? {
+            self.heartbeat_interval = heartbeat_interval;
+        }
+        if let Some(client_timeout) = env(lookup, "CHAT_CLIENT_TIMEOUT")? {
+            self.client_timeout = client_timeout;
+        }
+        if let Some(static_dir) = env(lookup, "CHAT_STATIC_DIR")? {
+            self.static_dir = static_dir;
+        }
+        if let Some(default_room) = env(lookup, "CHAT_DEFAULT_ROOM")? {
+            self.default_room = default_room;
+        }
+        if let Some(avatar_dir) = env(lookup, "CHAT_AVATAR_DIR")? {
+            self.avatar_dir = avatar_dir;
+        }
+        if let Some(avatar_size) = env(lookup, "CHAT_AVATAR_SIZE")? {
+            self.avatar_size = avatar_size;
+        }
+        if let Some(max_avatar_upload) = env(lookup, "CHAT_MAX_AVATAR_UPLOAD")? {
+            self.max_avatar_upload = max_avatar_upload;
+        }
+        if let Some(min_password_length) = env(lookup, "CHAT_MIN_PASSWORD_LENGTH")? {
+            self.min_password_length = min_password_length;
+        }
+        if let Some(reset_token_ttl) = env(lookup, "CHAT_RESET_TOKEN_TTL")? {
+            self.reset_token_ttl = reset_token_ttl;
+        }
+        if let Some(require_2fa_admins) = env_bool(lookup, "CHAT_REQUIRE_2FA_ADMINS")? {
+            self.require_2fa_admins = require_2fa_admins;
+        }
+        if let Some(require_2fa_moderators) = env_bool(lookup, "CHAT_REQUIRE_2FA_MODERATORS")? {
+            self.require_2fa_moderators = require_2fa_moderators;
+        }
+        if let Some(totp_issuer) = env(lookup, "CHAT_TOTP_ISSUER")? {
+            self.totp_issuer = totp_issuer;
+        }
+        if let Some(session_store) = env(lookup, "CHAT_SESSION_STORE")? {
+            self.session_store = session_store;
+        }
+        if let Some(session_lifetime) = env(lookup, "CHAT_SESSION_LIFETIME")? {
+            self.session_lifetime = session_lifetime;
+        }
+        if let Some(session_idle_timeout) = env(lookup, "CHAT_SESSION_IDLE_TIMEOUT")? {
+            self.session_idle_timeout = session_idle_timeout;
+        }
+        if let Some(cookie_secret) = env(lookup, "CHAT_COOKIE_SECRET")? {
+            self.cookie_secret = Some(cookie_secret);
+        }
+        if let Some(max_frame_size) = env(lookup, "CHAT_MAX_FRAME_SIZE")? {
+            self.max_frame_size = max_frame_size;
+        }
+        if let Some(max_message_length) = env(lookup, "CHAT_MAX_MESSAGE_LENGTH")? {
+            self.max_message_length = max_message_length;
+        }
+        if let Some(shards) = env(lookup, "CHAT_SHARDS")? {
+            self.shards = shards;
+        }
+        if let Some(shutdown_timeout) = env(lookup, "CHAT_SHUTDOWN_TIMEOUT")? {
+            self.shutdown_timeout = shutdown_timeout;
+        }
+        if let Some(reconnect_delay) = env(lookup, "CHAT_RECONNECT_DELAY")? {
+            self.reconnect_delay = reconnect_delay;
+        }
+        if let Some(broker_url) = env(lookup, "CHAT_BROKER_URL")? {
+            self.broker_url = Some(broker_url);
+        }
+        if let Some(broker_channel) = env(lookup, "CHAT_BROKER_CHANNEL")? {
+            self.broker_channel = broker_channel;
+        }
+        if let Some(cert) = env(lookup, "CHAT_TLS_CERT")? {
+            self.tls_mut().cert = cert;
+        }
+        if let Some(key) = env(lookup, "CHAT_TLS_KEY")? {
+            self.tls_mut().key = key;
+        }
+        if let Some(bind) = env(lookup, "CHAT_TLS_BIND")? {
+            self.tls_mut().bind = bind;
+        }
+        if let Some(redirect_http) = env_bool(lookup, "CHAT_TLS_REDIRECT_HTTP")? {
+            self.tls_mut().redirect_http = redirect_http;
+        }
+        Ok(())
+    }
+
+    fn apply_overrides(&mut self, overrides: &Overrides) {
+        if let Some(ref bind) = overrides.bind {
+            self.bind = bind.clone();
+        }
+        if let Some(workers) = overrides.workers {
+            self.workers = workers;
+        }
+        if let Some(ref database_url) = overrides.database_url {
+            self.database_url = database_url.clone();
+        }
+        if let Some(auto_migrate) = overrides.auto_migrate {
+            self.auto_migrate = auto_migrate;
+        }
+        if let Some(ref static_dir) = overrides.static_dir {
+            self.static_dir = static_dir.clone();
+        }
+        if let Some(ref default_room) = overrides.default_room {
+            self.default_room = default_room.clone();
+        }
+        if let Some(ref avatar_dir) = overrides.avatar_dir {
+            self.avatar_dir = avatar_dir.clone();
+        }
+        if let Some(shards) = overrides.shards {
+            self.shards = shards;
+        }
+        if let Some(ref broker_url) = overrides.broker_url {
+            self.broker_url = Some(broker_url.clone());
+        }
+        if let Some(ref cert) = overrides.tls_cert {
+            self.tls_mut().cert = cert.clone();
+        }
+        if let Some(ref key) = overrides.tls_key {
+            self.tls_mut().key = key.clone();
+        }
+        if let Some(ref bind) = overrides.tls_bind {
+            self.tls_mut().bind = bind.clone();
+        }
+        if let Some(redirect_http) = overrides.redirect_http {
+            self.tls_mut().redirect_http = redirect_http;
+        }
+    }
+
+    /// TLS settings, created with the defaults when a layer sets one
+    fn tls_mut(&mut self) -> &mut TlsConfig {
+        self.tls.get_or_insert_with(TlsConfig::default)
+    }
+
+    fn validate(&self, serving: bool) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if self.database_url.is_empty() {
+            return invalid("database_url must be set, or DATABASE_URL".to_owned());
+        }
+        if self.bind.to_socket_addrs().is_err() {
+            return invalid(format!("bind address {:?} is not host:port", self.bind));
+        }
+        if self.workers == 0 {
+            return invalid("workers must be at least 1".to_owned());
+        }
+        if self.heartbeat_interval == 0 || self.heartbeat_interval >= self.client_timeout {
+            return invalid("heartbeat_interval must be above 0 and below client_timeout".to_owned());
+        }
+        if serving && !self.static_dir.is_dir() {
+            return invalid(format!("static_dir {} is not a directory", self.static_dir.display()));
+        }
+        if self.default_room.is_empty() || self.default_room.contains(char::is_whitespace) {
+            return invalid(format!("default_room {:?} is not a room name", self.default_room));
+        }
+        if self.max_frame_size == 0 || self.max_message_length == 0 {
+            return invalid("max_frame_size and max_message_length must be above 0".to_owned());
+        }
+        if !(16..=1024).contains(&self.avatar_size) || self.max_avatar_upload == 0 {
+            return invalid("avatar_size must be 16 to 1024 and max_avatar_upload above 0".to_owned());
+        }
+        if !(1..=crate::password::PASSWORD_MAX).contains(&self.min_password_length) {
+            return invalid(format!(
+                "min_password_length must be 1 to {}",
+                crate::password::PASSWORD_MAX
+            ));
+        }
+        if self.reset_token_ttl == 0 {
+            return invalid("reset_token_ttl must be above 0".to_owned());
+        }
+        if self.totp_issuer.is_empty() || self.totp_issuer.contains(':') {
+            return invalid(format!("totp_issuer {:?} must not be empty or contain ':'", self.totp_issuer));
+        }
+        if self.session_store != "database" && self.session_store != "memory" {
+            return invalid(format!("session_store {:?} is not database or memory", self.session_store));
+        }
+        if self.session_lifetime == 0 || self.session_idle_timeout == 0 {
+            return invalid("session_lifetime and session_idle_timeout must be above 0".to_owned());
+        }
+        if self.cookie_secret.as_ref().is_some_and(|secret| secret.len() < 64) {
+            return invalid("cookie_secret must have at least 64 bytes".to_owned());
+        }
+        if self.shards == 0 {
+            return invalid("shards must be at least 1".to_owned());
+        }
+        if self.broker_channel.is_empty() {
+            return invalid("broker_channel must not be empty".to_owned());
+        }
+        if self.shutdown_timeout == 0 {
+            return invalid("shutdown_timeout must be above 0".to_owned());
+        }
+        let limits = [
+            ("session_messages", &self.limits.session_messages),
+            ("user_messages", &self.limits.user_messages),
+            ("room_messages", &self.limits.room_messages),
+            ("commands", &self.limits.commands),
+            ("login_ip", &self.limits.login_ip),
+            ("login_user", &self.limits.login_user),
+        ];
+        for (name, limit) in limits {
+            let valid = limit.rate > 0.0 && limit.burst >= 1.0;
+            if !valid {
+                return invalid(format!("limits.{name} needs a rate above 0 and a burst of at least 1"));
+            }
+        }
+        if self.limits.flood_strikes == 0 {
+            return invalid("limits.flood_strikes must be at least 1".to_owned());
+        }
+        if let Some(ref tls) = self.tls {
+            if serving && (!tls.cert.is_file() || !tls.key.is_file()) {
+                return invalid("tls needs both a cert and a key file".to_owned());
+            }
+            if tls.bind.to_socket_addrs().is_err() {
+                return invalid(format!("tls bind address {:?} is not host:port", tls.bind));
+            }
+            if tls.bind == self.bind {
+                return invalid("tls bind address must differ from bind".to_owned());
+            }
+            if tls.reload_interval == 0 {
+                return invalid("tls reload_interval must be above 0".to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+
+    pub fn reconnect_delay(&self) -> Duration {
+        Duration::from_secs(self.reconnect_delay)
+    }
+
+    pub fn reset_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.reset_token_ttl as i64)
+    }
+
+    pub fn session_lifetime(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.session_lifetime as i64)
+    }
+
+    pub fn session_idle_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.session_idle_timeout as i64)
+    }
+
+    /// Path of a file inside `static_dir`
+    pub fn static_file(&self, name: &str) -> PathBuf {
+        self.static_dir.join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn layers_take_precedence_in_order() {
+        let path = std::env::temp_dir().join(format!("verdant_chat_test_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "bind = \"127.0.0.1:1000\"\nworkers = 3\ndefault_room = \"file\"\ndatabase_url = \"memory://\"\n",
+        )
+        .unwrap();
+        let vars: HashMap<&str, &str> = [("CHAT_BIND", "127.0.0.1:2000"), ("CHAT_DEFAULT_ROOM", "env")].into();
+        let overrides = Overrides {
+            config: Some(path.clone()),
+            default_room: Some("flag".to_owned()),
+            ..Overrides::default()
+        };
+        let config = Config::load_with(&overrides, false, &|name| vars.get(name).map(|value| value.to_string()));
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        // the file beats the defaults, the environment the file and the
+        // command line everything
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.bind, "127.0.0.1:2000");
+        assert_eq!(config.default_room, "flag");
+    }
+}
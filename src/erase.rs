@@ -0,0 +1,40 @@
+//! Hard erase job. Deleted accounts are kept for a grace period so admins
+//! can look into what happened, afterwards they get erased for good.
+
+use std::time::Duration;
+
+use actix_web::{rt, web};
+
+use crate::store::{ChatStore, QueryError};
+
+/// How often the job looks for accounts to erase
+const ERASE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long deleted accounts are kept before they get erased
+const ERASE_AFTER_DAYS: i64 = 30;
+
+/// Start erasing deleted accounts in the background
+pub fn spawn(store: web::Data<dyn ChatStore>) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(ERASE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let store = store.clone();
+            match web::block(move || erase_expired(&**store)).await {
+                Ok(Ok(0)) => (),
+                Ok(Ok(count)) => log::info!("erased {count} deleted users"),
+                Ok(Err(err)) => log::error!("failed to erase deleted users: {err}"),
+                Err(err) => log::error!("failed to erase deleted users: {err}"),
+            }
+        }
+    });
+}
+
+fn erase_expired(store: &dyn ChatStore) -> Result<usize, QueryError> {
+    let before = chrono::Utc::now().naive_utc() - chrono::Duration::days(ERASE_AFTER_DAYS);
+    let mut count = 0;
+    for user_id in store.query_erasable_users(before)? {
+        count += store.erase_user(&user_id)?;
+    }
+    Ok(count)
+}
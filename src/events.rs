@@ -0,0 +1,46 @@
+//! Events inside an instance, fanned out by the actix-broker system broker.
+//! Actors subscribe with `subscribe_system_async::<SystemEvent>`, events are
+//! handed out with `issue`. `ChatServer` relays them to the other instances.
+//!
+//! Only changes made through a running server are issued. The admin
+//! subcommands of `cli` run in a process of their own, live sessions don't
+//! see what they change, e.g. an archived room, until they read it from the
+//! store again.
+
+use actix::prelude::*;
+use actix_broker::{Broker, SystemBroker};
+use serde::{Deserialize, Serialize};
+
+/// Something changed that live sessions or other subsystems may care about
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SystemEvent {
+    /// The account was deleted
+    UserDeleted { id: String },
+    /// The account was suspended
+    UserSuspended { id: String },
+    /// The password was changed or reset, earlier logins are void
+    PasswordChanged { id: String },
+    /// The user logged out of or revoked the login session `session`
+    SessionRevoked { id: String, session: String },
+    /// The user's role in the room changed, one of the `ROLE_*` of `models`
+    RoleChanged { id: String, room: String, role: i32 },
+    /// The user changed how others see them, `avatar` is the url of the
+    /// image
+    ProfileChanged {
+        id: String,
+        name: String,
+        avatar: Option<String>,
+        status: Option<String>,
+    },
+}
+
+/// Hand an event to every subscriber. Needs a running actix system, so
+/// commands on the blocking pool leave this to their session.
+///
+/// Actors use this rather than `BrokerIssue::issue_system_async`, which
+/// skips every actor of the issuer's type, not only the issuer.
+pub fn issue(event: SystemEvent) {
+    Broker::<SystemBroker>::issue_async(event);
+}
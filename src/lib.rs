@@ -0,0 +1,24 @@
+#[macro_use]
+extern crate diesel;
+pub mod models;
+pub mod pg_schema;
+pub mod schema;
+
+pub mod api;
+pub mod broker;
+pub mod cli;
+pub mod command;
+pub mod config;
+pub mod erase;
+pub mod events;
+pub mod login_session;
+pub mod migrate;
+pub mod password;
+pub mod profile;
+pub mod server;
+pub mod session;
+pub mod shutdown;
+pub mod store;
+pub mod throttle;
+pub mod tls;
+pub mod two_factor;
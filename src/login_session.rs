@@ -0,0 +1,211 @@
+//! Server side login sessions for `SessionMiddleware`. The cookie only
+//! carries a random session key, the entries are kept in a `ChatStore`
+//! under its digest: the database, or a store in memory of its own with
+//! `session_store = "memory"`. Users list and revoke their sessions
+//! through `api`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use actix_web::web;
+use rand::{distributions::Alphanumeric, Rng};
+use sha256::digest;
+
+use crate::config::Config;
+use crate::models::LoginSession;
+use crate::store::{ChatStore, MemoryStore, QueryError};
+
+/// Session entry with the id of the session, added when it is loaded.
+/// Handlers read it to tell their session apart from the user's others.
+pub const SESSION_ID_KEY: &str = "session_id";
+
+/// Session entry with the user agent of the login
+pub const USER_AGENT_KEY: &str = "user_agent";
+
+/// Session entry with the client address of the login
+pub const IP_KEY: &str = "ip";
+
+/// Session entry with the user's session stamp at login
+pub const STAMP_KEY: &str = "session_stamp";
+
+/// Session entry `actix_identity` keeps the logged in user id under
+const IDENTITY_KEY: &str = "actix_identity.user_id";
+
+/// Prefix of every session entry of `actix_identity`
+const IDENTITY_PREFIX: &str = "actix_identity.";
+
+/// Characters in a session key
+const KEY_LENGTH: usize = 64;
+
+/// Longest user agent kept, in characters
+const USER_AGENT_MAX: usize = 255;
+
+/// Seconds between updates of `last_seen` while a session is in use, not
+/// every request has to write
+const TOUCH_INTERVAL: i64 = 60;
+
+#[derive(Clone)]
+pub struct LoginSessions {
+    store: Arc<dyn ChatStore>,
+    lifetime: chrono::Duration,
+    idle_timeout: chrono::Duration,
+}
+
+impl LoginSessions {
+    /// Sessions kept in `database`, or in memory if the configuration says so
+    pub fn new(config: &Config, database: Arc<dyn ChatStore>) -> Self {
+        let store = match config.session_store.as_str() {
+            "memory" => Arc::new(MemoryStore::default()),
+            _ => database,
+        };
+        LoginSessions {
+            store,
+            lifetime: config.session_lifetime(),
+            idle_timeout: config.session_idle_timeout(),
+        }
+    }
+
+    /// Sessions the user is logged in with, most recently used first
+    pub fn list(&self, user_id: &str) -> Result<Vec<LoginSession>, QueryError> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut sessions = self.store.query_login_sessions(user_id)?;
+        sessions.retain(|session| session.expires > now && session.last_seen > now - self.idle_timeout);
+        Ok(sessions)
+    }
+
+    /// End one of the user's sessions, false if there is no such session
+    pub fn revoke(&self, user_id: &str, id: &str) -> Result<bool, QueryError> {
+        Ok(self.store.revoke_login_session(user_id, id)? > 0)
+    }
+
+    /// End every session of the user but `keep`
+    pub fn revoke_all(&self, user_id: &str, keep: Option<&str>) -> Result<usize, QueryError> {
+        self.store.delete_login_sessions(user_id, keep)
+    }
+
+    /// Entries of the session, `None` if it is unknown, expired or was idle
+    /// for too long
+    fn load_state(&self, key: &str) -> Result<Option<HashMap<String, String>>, QueryError> {
+        let key_digest = digest(key);
+        let Some(session) = self.store.query_login_session(&key_digest)? else {
+            return Ok(None);
+        };
+        let now = chrono::Utc::now().naive_utc();
+        if session.expires <= now || session.last_seen <= now - self.idle_timeout {
+            self.store.delete_login_session(&key_digest)?;
+            return Ok(None);
+        }
+        if now - session.last_seen >= chrono::Duration::seconds(TOUCH_INTERVAL) {
+            self.store.touch_login_session(&key_digest, now)?;
+        }
+        // a session that doesn't parse is as good as none
+        let Ok(mut state) = serde_json::from_str::<HashMap<String, String>>(&session.state) else {
+            log::warn!("session {} has unreadable state, dropped", session.id);
+            self.store.delete_login_session(&key_digest)?;
+            return Ok(None);
+        };
+        state.insert(SESSION_ID_KEY.to_owned(), serde_json::json!(session.id).to_string());
+        Ok(Some(state))
+    }
+
+    /// Keep the entries of a new session, returns its key
+    fn save_state(&self, state: HashMap<String, String>) -> Result<String, QueryError> {
+        let now = chrono::Utc::now().naive_utc();
+        self.store.delete_stale_login_sessions(now, now - self.idle_timeout)?;
+        let key: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(KEY_LENGTH)
+            .map(char::from)
+            .collect();
+        let mut session = LoginSession::from_details(digest(key.as_str()), "", self.lifetime);
+        fill(&mut session, state);
+        self.store.update_login_session(&session)?;
+        Ok(key)
+    }
+
+    /// Replace the entries of a session. One that was revoked or expired
+    /// while the request ran is saved as a new session with a new key, but
+    /// logged out: ending a session must not be undone by a late write.
+    fn update_state(&self, key: String, mut state: HashMap<String, String>) -> Result<String, QueryError> {
+        let Some(mut session) = self.store.query_login_session(&digest(key.as_str()))? else {
+            state.retain(|name, _| {
+                !name.starts_with(IDENTITY_PREFIX) && ![STAMP_KEY, USER_AGENT_KEY, IP_KEY].contains(&name.as_str())
+            });
+            return self.save_state(state);
+        };
+        fill(&mut session, state);
+        session.last_seen = chrono::Utc::now().naive_utc();
+        self.store.update_login_session(&session)?;
+        Ok(key)
+    }
+}
+
+/// Entry of the session state, the values are JSON
+fn entry(state: &HashMap<String, String>, key: &str) -> Option<String> {
+    state.get(key).and_then(|value| serde_json::from_str(value).ok())
+}
+
+/// Store the entries in `session` and pick out the columns shown to the
+/// user
+fn fill(session: &mut LoginSession, mut state: HashMap<String, String>) {
+    state.remove(SESSION_ID_KEY);
+    session.user_id = entry(&state, IDENTITY_KEY);
+    session.user_agent = entry(&state, USER_AGENT_KEY).map(|agent| agent.chars().take(USER_AGENT_MAX).collect());
+    session.ip = entry(&state, IP_KEY);
+    session.state = serde_json::json!(state).to_string();
+}
+
+/// Run a store call on the blocking thread pool
+async fn run<T, F>(f: F) -> Result<T, anyhow::Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, QueryError> + Send + 'static,
+{
+    Ok(web::block(f).await.map_err(QueryError::from)??)
+}
+
+/// Expiry follows `session_lifetime` and `session_idle_timeout`, the ttl
+/// the middleware asks for only sets how long the cookie lives
+#[async_trait::async_trait(?Send)]
+impl SessionStore for LoginSessions {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let (sessions, key) = (self.clone(), session_key.as_ref().to_owned());
+        run(move || sessions.load_state(&key)).await.map_err(LoadError::Other)
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, _: &Duration) -> Result<SessionKey, SaveError> {
+        let sessions = self.clone();
+        let key = run(move || sessions.save_state(session_state))
+            .await
+            .map_err(SaveError::Other)?;
+        key.try_into().map_err(|err| SaveError::Other(anyhow::Error::new(err)))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        _: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let (sessions, key) = (self.clone(), session_key.as_ref().to_owned());
+        let key = run(move || sessions.update_state(key, session_state))
+            .await
+            .map_err(UpdateError::Other)?;
+        key.try_into().map_err(|err| UpdateError::Other(anyhow::Error::new(err)))
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, _: &Duration) -> Result<(), anyhow::Error> {
+        let (store, key) = (self.store.clone(), session_key.as_ref().to_owned());
+        let now = chrono::Utc::now().naive_utc();
+        run(move || store.touch_login_session(&digest(key), now)).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        let (store, key) = (self.store.clone(), session_key.as_ref().to_owned());
+        run(move || store.delete_login_session(&digest(key))).await?;
+        Ok(())
+    }
+}
@@ -1,31 +1,23 @@
-use std::{
-    sync::{
-        atomic::AtomicUsize,
-        Arc,
-    },
-};
+use std::sync::{atomic::AtomicUsize, Arc};
 
 use actix::*;
 use actix_files::{Files};
 use actix_identity::{IdentityMiddleware};
-use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+use actix_session::{config::PersistentSession, SessionMiddleware};
 use actix_web::{
+    dev::{Service, ServiceResponse},
+    http::header,
     middleware::Logger, web, App,
-    HttpServer,
+    HttpResponse, HttpServer,
 };
-//use actix::*;
-use diesel::prelude::*;
-use diesel::r2d2::{self, ConnectionManager};
-
-#[macro_use]
-extern crate diesel;
-mod models;
-mod schema;
+use clap::Parser;
+use futures::future::{self, Either, TryFutureExt};
 
-mod api;
-mod query;
-mod server;
-mod session;
+use verdant_chat::cli::{self, Action, Cli};
+use verdant_chat::{
+    api, broker, config, erase, login_session::LoginSessions, migrate, profile, server, shutdown, store, throttle, tls,
+};
+//use actix::*;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -35,46 +27,159 @@ async fn main() -> std::io::Result<()> {
         "RUST_LOG",
         "simple-auth-server=debug,actix_web=info,actix_server=info",
     );
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let cli = Cli::parse();
+    let serving = matches!(cli.action, None | Some(Action::Serve));
+    let config = config::Config::load(&cli.overrides, serving)?;
+
+    // mysql://, postgres://, sqlite:// or memory://
+    let store = store::connect(&config.database_url).expect("Failed to open the store.");
 
-    // create db connection pool
-    let manager = ConnectionManager::<MysqlConnection>::new(database_url);
+    match cli.action {
+        None | Some(Action::Serve) => {}
+        // admin commands work on the schema as it is, run `migrate up` first
+        Some(Action::Admin(action)) => return cli::run(action, &*store, &config),
+    }
 
-    let pool: models::Pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
+    // pending migrations are applied unless auto_migrate is off
+    migrate::check(&*store, config.auto_migrate)?;
+
+    // login sessions, kept in the store unless session_store is memory
+    let sessions = web::Data::new(LoginSessions::new(&config, store.clone()));
+    let store = web::Data::from(store);
+    erase::spawn(store.clone());
+
+    // uploaded avatars are kept here
+    std::fs::create_dir_all(&config.avatar_dir)?;
 
     // set up applications state
     // keep a count of the number of visitors
     let app_state = Arc::new(AtomicUsize::new(0));
 
+    // failed room password attempts, shared by all workers
+    let join_throttle = web::Data::new(throttle::Throttle::default());
+    let rate_limits = web::Data::new(throttle::RateLimits::new(&config.limits));
+
+    // redis:// or, without a url, only this instance
+    let broker = broker::connect(config.broker_url.as_deref(), &config.broker_channel)
+        .map_err(std::io::Error::other)?;
+
     // start chat server actor
-    let server = server::ChatServer::new(app_state.clone()).start();
+    let server = server::ChatServer::new(
+        app_state.clone(),
+        config.default_room.clone(),
+        broker,
+        config.shards,
+    )
+    .start();
+
+    let tls = match config.tls {
+        Some(ref tls) => {
+            let resolver = Arc::new(tls::CertResolver::load(&tls.cert, &tls.key)?);
+            tls::spawn_reload(resolver.clone(), tls.reload_interval());
+            Some((tls.clone(), resolver))
+        }
+        None => None,
+    };
+    // plain requests are only redirected when the HTTP listener is kept
+    // next to the HTTPS one for that
+    let redirect_port = tls.as_ref().filter(|(tls, _)| tls.redirect_http).map(|(tls, _)| tls.port());
 
-    log::info!("starting HTTP server at http://localhost:8080");
+    let bind = config.bind.clone();
+    let workers = config.workers;
+    let reconnect_in = config.reconnect_delay();
+    let deadline = config.shutdown_timeout();
+    let chat = server.clone();
+    // one key for every worker, cookies are handed to any of them
+    let secret_key = api::secret_key(config.cookie_secret.as_deref());
+    let session_ttl = actix_web::cookie::time::Duration::seconds(config.session_lifetime as i64);
+    let config = web::Data::new(config);
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(config.clone())
+            .app_data(store.clone())
             .app_data(web::Data::from(app_state.clone()))
             .app_data(web::Data::new(server.clone()))
+            .app_data(join_throttle.clone())
+            .app_data(rate_limits.clone())
+            .app_data(sessions.clone())
             .service(web::resource("/").route(web::get().to(api::index)))
             .service(web::resource("/login").route(web::post().to(api::login)))
+            .service(web::resource("/logout").route(web::post().to(api::logout)))
+            .service(
+                web::resource("/login/2fa")
+                    .route(web::get().to(api::login_2fa))
+                    .route(web::post().to(api::login_2fa_post)),
+            )
             .service(web::resource("/rigister").route(web::get().to(api::rigister)))
             .service(web::resource("/rigister_post").route(web::post().to(api::rigister_post)))
-            .service(Files::new("/static", "./static"))
+            .service(Files::new("/static", &config.static_dir))
+            .service(Files::new(profile::AVATAR_PATH, &config.avatar_dir))
             .service(web::resource("/chatroom").to(api::chatroom))
             .route("/count", web::get().to(api::get_count))
             .route("/ws", web::get().to(api::chat_route))
+            .route("/api/v1/audit", web::get().to(api::audit_log))
+            .route("/api/v1/password", web::post().to(api::change_password))
+            .route("/api/v1/password/reset", web::post().to(api::reset_password))
+            .route("/api/v1/users/{id}/password-reset", web::post().to(api::issue_password_reset))
+            .route("/api/v1/sessions", web::get().to(api::list_sessions))
+            .route("/api/v1/sessions/{id}", web::delete().to(api::revoke_session))
+            .route("/api/v1/2fa/enroll", web::post().to(api::enroll_2fa))
+            .route("/api/v1/2fa/confirm", web::post().to(api::confirm_2fa))
+            .route("/api/v1/2fa/recovery-codes", web::post().to(api::regenerate_recovery_codes))
+            .route("/api/v1/2fa/disable", web::post().to(api::disable_2fa))
+            .route("/api/v1/users/{id}/2fa", web::delete().to(api::reset_2fa))
+            .route("/api/v1/users/{id}/2fa/required", web::put().to(api::require_2fa))
+            .service(
+                web::resource("/api/v1/users/{id}")
+                    .route(web::get().to(api::get_profile))
+                    .route(web::patch().to(api::patch_profile)),
+            )
+            .service(
+                web::resource("/api/v1/users/{id}/avatar")
+                    .app_data(web::PayloadConfig::new(config.max_avatar_upload))
+                    .route(web::put().to(api::put_avatar))
+                    .route(web::delete().to(api::delete_avatar)),
+            )
+            .wrap_fn(move |req, srv| match tls::redirect_location(&req, redirect_port) {
+                Some(location) => {
+                    let res = HttpResponse::PermanentRedirect()
+                        .insert_header((header::LOCATION, location))
+                        .finish();
+                    Either::Left(future::ok(req.into_response(res).map_into_right_body()))
+                }
+                None => Either::Right(srv.call(req).map_ok(ServiceResponse::map_into_left_body)),
+            })
             .wrap(Logger::default())
             .wrap(IdentityMiddleware::default())
-            .wrap(SessionMiddleware::new(
-                CookieSessionStore::default(),
-                api::secret_key().clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(sessions.get_ref().clone(), secret_key.clone())
+                    .session_lifecycle(PersistentSession::default().session_ttl(session_ttl))
+                    .build(),
+            )
     })
-    .workers(2)
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    .workers(workers)
+    // signals are handled by `shutdown` so sessions can be closed first
+    .disable_signals()
+    .shutdown_timeout(deadline.as_secs());
+
+    let server = match tls {
+        Some((tls, resolver)) => {
+            log::info!("starting HTTPS server at https://{}", tls.bind);
+            let server = server.bind_rustls(&tls.bind, tls::server_config(resolver))?;
+            if tls.redirect_http {
+                log::info!("redirecting http://{bind} to HTTPS");
+                server.bind(bind)?
+            } else {
+                server
+            }
+        }
+        None => {
+            log::info!("starting HTTP server at http://{bind}");
+            server.bind(bind)?
+        }
+    };
+    let server = server.run();
+    shutdown::spawn(server.handle(), chat, reconnect_in, deadline);
+    server.await
 }
@@ -0,0 +1,60 @@
+//! `migrate` subcommand and the schema check done before serving.
+
+use std::io;
+
+use crate::cli::MigrateAction;
+use crate::store::ChatStore;
+
+fn other<E: std::fmt::Display>(err: E) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+/// `migrate status|up|down`
+pub fn command(store: &dyn ChatStore, action: MigrateAction) -> io::Result<()> {
+    match action {
+        MigrateAction::Status => {
+            for (version, applied) in store.migrations().map_err(other)? {
+                println!("[{}] {version}", if applied { "x" } else { " " });
+            }
+        }
+        MigrateAction::Up => {
+            let applied = store.migrate_up().map_err(other)?;
+            if applied.is_empty() {
+                println!("nothing to apply");
+            }
+            for version in applied {
+                println!("applied {version}");
+            }
+        }
+        MigrateAction::Down => match store.migrate_down().map_err(other)? {
+            Some(version) => println!("reverted {version}"),
+            None => println!("nothing to revert"),
+        },
+    }
+    Ok(())
+}
+
+/// Bring the schema up to date before serving. With `auto_migrate` off
+/// pending migrations are an error instead.
+pub fn check(store: &dyn ChatStore, auto_migrate: bool) -> io::Result<()> {
+    if auto_migrate {
+        store.migrate_up().map_err(other)?;
+        return Ok(());
+    }
+    let pending: Vec<_> = store
+        .migrations()
+        .map_err(other)?
+        .into_iter()
+        .filter(|(_, applied)| !applied)
+        .map(|(version, _)| version)
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(other(format!(
+            "database schema is out of date, pending migrations: {}. \
+             Run `verdant_chat migrate up` or set AUTO_MIGRATE=true",
+            pending.join(", ")
+        )))
+    }
+}
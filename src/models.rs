@@ -1,18 +1,29 @@
 // models.rs
 use super::schema::*;
-use diesel::{r2d2::ConnectionManager, Insertable, MysqlConnection, Queryable};
+use diesel::{Insertable, Queryable};
 use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
-// type alias to use in multiple places
-pub type Pool = r2d2::Pool<ConnectionManager<MysqlConnection>>;
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "users"]
 pub struct User {
     pub uuid: String,
     pub name: String,
     pub password: String,
     pub permission_id: i32,
+    pub state: i32,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    /// shown instead of `name` when set, unique like `name`
+    pub display_name: Option<String>,
+    /// changes with the password, logins made before no longer count
+    pub session_stamp: String,
+    /// `name_key` of the display name, unique so names differing only in
+    /// case can't be told apart from each other
+    pub display_key: Option<String>,
+    /// set by an admin, two factor authentication is required whatever the
+    /// user's role
+    pub require_2fa: bool,
 }
 impl User {
     pub fn from_details<S: Into<String>, T: Into<String>>(user: S, pass: T) -> Self {
@@ -21,25 +32,327 @@ impl User {
             password: pass.into(),
             uuid: Uuid::new_v4().clone().to_string(),
             permission_id: 0,
+            state: UserState::Active.as_i32(),
+            deleted_at: None,
+            display_name: None,
+            session_stamp: Uuid::new_v4().to_string(),
+            display_key: None,
+            require_2fa: false,
+        }
+    }
+
+    /// Name shown next to the user's messages
+    pub fn shown_name(&self) -> &str {
+        if UserState::from_i32(self.state) == UserState::Deleted {
+            DELETED_USER_NAME
+        } else {
+            self.display_name.as_deref().unwrap_or(&self.name)
+        }
+    }
+}
+
+/// Longest display name, in characters
+pub const DISPLAY_NAME_MAX: usize = 32;
+
+/// Display names are 2 to `DISPLAY_NAME_MAX` ASCII letters, digits, `_`,
+/// `-` or `.`, words may be separated by single spaces. Other scripts have
+/// letters that look just like ASCII ones.
+pub fn valid_display_name(name: &str) -> bool {
+    let len = name.chars().count();
+    (2..=DISPLAY_NAME_MAX).contains(&len)
+        && name.split(' ').all(|word| {
+            !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        })
+        && !name.eq_ignore_ascii_case(DELETED_USER_NAME)
+}
+
+/// Form of a user or display name two names are compared in, they are
+/// taken when they only differ in case
+pub fn name_key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Text as it is stored and sent on: NFC normalized, without control
+/// characters and surrounding whitespace. Line breaks and tabs are kept
+/// where `multiline`.
+pub fn clean_text(text: &str, multiline: bool) -> String {
+    let text: String = text
+        .nfc()
+        .filter(|&c| !c.is_control() || (multiline && matches!(c, '\n' | '\t')))
+        .collect();
+    text.trim().to_owned()
+}
+
+/// What a user tells others about themselves, users without a row have an
+/// empty profile
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "user_profiles"]
+pub struct Profile {
+    pub user_id: String,
+    /// file name of the resized image in the avatar directory
+    pub avatar: Option<String>,
+    pub bio: Option<String>,
+    /// short custom status like "in a meeting"
+    pub status: Option<String>,
+    /// IANA time zone name like "Europe/Berlin"
+    pub timezone: Option<String>,
+}
+impl Profile {
+    pub fn from_details<S: Into<String>>(user: S) -> Self {
+        Profile {
+            user_id: user.into(),
+            ..Profile::default()
+        }
+    }
+}
+
+/// Longest bio, in characters
+pub const BIO_MAX: usize = 500;
+
+/// Longest status text, in characters
+pub const STATUS_MAX: usize = 128;
+
+/// One time token an admin issued to reset a user's password, a user has
+/// at most one
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "password_resets"]
+pub struct PasswordReset {
+    pub user_id: String,
+    /// sha256 digest of the token, the token itself is only shown once
+    pub token: String,
+    pub expires: chrono::NaiveDateTime,
+}
+impl PasswordReset {
+    pub fn from_details<S: Into<String>, T: Into<String>>(user: S, token: T, duration: chrono::Duration) -> Self {
+        PasswordReset {
+            user_id: user.into(),
+            token: token.into(),
+            expires: chrono::Utc::now().naive_utc() + duration,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+/// TOTP second factor of a user, only asked for once it is enabled
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "user_totp"]
+pub struct Totp {
+    pub user_id: String,
+    /// base32 shared secret
+    pub secret: String,
+    /// set once the user confirmed a code, until then the secret is unused
+    pub enabled_at: Option<chrono::NaiveDateTime>,
+    /// time step of the last code accepted, codes can't be used twice
+    pub last_step: i64,
+}
+impl Totp {
+    pub fn from_details<S: Into<String>, T: Into<String>>(user: S, secret: T) -> Self {
+        Totp {
+            user_id: user.into(),
+            secret: secret.into(),
+            enabled_at: None,
+            last_step: 0,
+        }
+    }
+}
+
+/// Single use code to log in without the TOTP device
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCode {
+    pub user_id: String,
+    /// sha256 digest of the code
+    pub code: String,
+}
+
+/// Server side state of a browser session. The cookie only carries the
+/// session key, rows are found by its digest.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "login_sessions"]
+pub struct LoginSession {
+    /// sha256 digest of the session key
+    pub key_digest: String,
+    /// id the user sees when listing or revoking their sessions
+    pub id: String,
+    /// set once someone logged in with the session
+    pub user_id: Option<String>,
+    /// JSON object of the session entries
+    pub state: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created: chrono::NaiveDateTime,
+    pub last_seen: chrono::NaiveDateTime,
+    pub expires: chrono::NaiveDateTime,
+}
+impl LoginSession {
+    pub fn from_details<S: Into<String>, T: Into<String>>(key_digest: S, state: T, lifetime: chrono::Duration) -> Self {
+        let now = chrono::Utc::now().naive_utc();
+        LoginSession {
+            key_digest: key_digest.into(),
+            id: Uuid::new_v4().to_string(),
+            user_id: None,
+            state: state.into(),
+            user_agent: None,
+            ip: None,
+            created: now,
+            last_seen: now,
+            expires: now + lifetime,
+        }
+    }
+}
+
+/// Messages of erased users are attributed to this account
+pub const DELETED_USER_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Actor of audit entries made with the admin subcommands of the command
+/// line, no account has this id
+pub const CLI_ACTOR_ID: &str = "00000000-0000-0000-0000-000000000001";
+
+/// Name shown for senders whose account was deleted
+pub const DELETED_USER_NAME: &str = "deleted user";
+
+/// Lifecycle of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserState {
+    Active,
+    /// locked by an admin, can't log in
+    Suspended,
+    /// closed by the user, logging in again reactivates it
+    Deactivated,
+    /// removed, kept only until it gets erased
+    Deleted,
+}
+impl UserState {
+    pub fn from_i32(value: i32) -> Self {
+        match value {
+            1 => UserState::Suspended,
+            2 => UserState::Deactivated,
+            3 => UserState::Deleted,
+            _ => UserState::Active,
+        }
+    }
+    pub fn as_i32(self) -> i32 {
+        match self {
+            UserState::Active => 0,
+            UserState::Suspended => 1,
+            UserState::Deactivated => 2,
+            UserState::Deleted => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "rooms"]
 pub struct Room {
     pub id: i32,
     pub rname: String,
+    pub visibility: i32,
+    /// sha256 digest of the join password, rooms without one are open
+    pub password: Option<String>,
+    /// archived rooms keep their history but can't be joined or written to
+    pub archived_at: Option<chrono::NaiveDateTime>,
 }
 impl Room {
     pub fn from_details<T: Into<String>>(rname: T) -> Self {
         Room {
             rname: rname.into(),
             id: 0,
+            visibility: Visibility::Public.as_i32(),
+            password: None,
+            archived_at: None,
+        }
+    }
+}
+
+/// Who can see and enter a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// listed to everyone, anyone can join
+    Public,
+    /// only listed to members, joining needs an invite or an approved request
+    Private,
+    /// listed to everyone, joining needs an invite or an approved request
+    Invite,
+}
+impl Visibility {
+    pub fn from_i32(value: i32) -> Self {
+        match value {
+            1 => Visibility::Private,
+            2 => Visibility::Invite,
+            _ => Visibility::Public,
+        }
+    }
+    pub fn as_i32(self) -> i32 {
+        match self {
+            Visibility::Public => 0,
+            Visibility::Private => 1,
+            Visibility::Invite => 2,
+        }
+    }
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "public" => Some(Visibility::Public),
+            "private" => Some(Visibility::Private),
+            "invite" => Some(Visibility::Invite),
+            _ => None,
+        }
+    }
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Private => "private",
+            Visibility::Invite => "invite",
+        }
+    }
+}
+
+pub const ROLE_MEMBER: i32 = 0;
+pub const ROLE_MODERATOR: i32 = 1;
+pub const ROLE_OWNER: i32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "room_members"]
+pub struct RoomMember {
+    pub room_id: i32,
+    pub user_id: String,
+    pub role: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "room_invites"]
+pub struct RoomInvite {
+    pub room_id: i32,
+    pub user_id: String,
+    pub inviter_id: String,
+    pub time: chrono::NaiveDateTime,
+}
+impl RoomInvite {
+    pub fn from_details<S: Into<String>, T: Into<String>>(room: i32, user: S, inviter: T) -> Self {
+        RoomInvite {
+            room_id: room,
+            user_id: user.into(),
+            inviter_id: inviter.into(),
+            time: chrono::Utc::now().naive_utc(),
         }
     }
 }
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "room_requests"]
+pub struct JoinRequest {
+    pub room_id: i32,
+    pub user_id: String,
+    pub time: chrono::NaiveDateTime,
+}
+impl JoinRequest {
+    pub fn from_details<S: Into<String>>(room: i32, user: S) -> Self {
+        JoinRequest {
+            room_id: room,
+            user_id: user.into(),
+            time: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "messages"]
 pub struct Mess {
     pub uuid: String,
@@ -52,10 +365,101 @@ impl Mess {
     pub fn from_details<S: Into<String>, T: Into<String>>(sender: S, cont: T, room: i32) -> Self {
         Mess {
             uuid: Uuid::new_v4().clone().to_string(),
-            time: chrono::Local::now().naive_local() + chrono::Duration::hours(24),
+            time: chrono::Utc::now().naive_utc(),
             content: cont.into(),
             sender_id: sender.into(),
             room_id: room.into()
         }
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "room_bans"]
+pub struct RoomBan {
+    pub room_id: i32,
+    pub user_id: String,
+    pub moderator_id: String,
+    pub reason: Option<String>,
+    pub time: chrono::NaiveDateTime,
+    /// bans without expiry are permanent
+    pub expires: Option<chrono::NaiveDateTime>,
+}
+impl RoomBan {
+    pub fn from_details<S: Into<String>, T: Into<String>>(
+        room: i32,
+        user: S,
+        moderator: T,
+        reason: Option<String>,
+        duration: Option<chrono::Duration>,
+    ) -> Self {
+        let now = chrono::Utc::now().naive_utc();
+        RoomBan {
+            room_id: room,
+            user_id: user.into(),
+            moderator_id: moderator.into(),
+            reason,
+            time: now,
+            expires: duration.and_then(|duration| now.checked_add_signed(duration)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "room_mutes"]
+pub struct RoomMute {
+    pub room_id: i32,
+    pub user_id: String,
+    pub moderator_id: String,
+    pub time: chrono::NaiveDateTime,
+    /// mutes without expiry last until lifted
+    pub expires: Option<chrono::NaiveDateTime>,
+}
+impl RoomMute {
+    pub fn from_details<S: Into<String>, T: Into<String>>(
+        room: i32,
+        user: S,
+        moderator: T,
+        duration: Option<chrono::Duration>,
+    ) -> Self {
+        let now = chrono::Utc::now().naive_utc();
+        RoomMute {
+            room_id: room,
+            user_id: user.into(),
+            moderator_id: moderator.into(),
+            time: now,
+            expires: duration.and_then(|duration| now.checked_add_signed(duration)),
+        }
+    }
+}
+
+/// One moderation or admin action, rows are never updated or deleted
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "audit_log"]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor_id: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub room_id: Option<i32>,
+    pub reason: Option<String>,
+    pub time: chrono::NaiveDateTime,
+}
+impl AuditEntry {
+    pub fn from_details<S: Into<String>, T: Into<String>>(
+        actor: S,
+        action: T,
+        target: Option<String>,
+        room: Option<i32>,
+        reason: Option<String>,
+    ) -> Self {
+        AuditEntry {
+            id: 0,
+            actor_id: actor.into(),
+            action: action.into(),
+            target,
+            room_id: room,
+            reason,
+            time: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Password policy, password changes and one time reset tokens. The HTTP
+//! endpoints are in `api`.
+
+use derive_more::Display;
+use rand::{distributions::Alphanumeric, Rng};
+use sha256::digest;
+
+use crate::config::Config;
+use crate::models::{AuditEntry, PasswordReset, User, UserState};
+use crate::store::{ChatStore, QueryError};
+
+/// Longest password accepted, in characters
+pub const PASSWORD_MAX: usize = 128;
+
+/// Characters in a reset token
+const TOKEN_LENGTH: usize = 32;
+
+/// Error of a password request
+#[derive(Debug, Display)]
+pub enum PasswordError {
+    #[display(fmt = "log in first")]
+    Unauthorized,
+    #[display(fmt = "only admins may reset passwords")]
+    Forbidden,
+    #[display(fmt = "no such user")]
+    NotFound,
+    #[display(fmt = "the current password is wrong")]
+    WrongPassword,
+    #[display(fmt = "{_0}")]
+    Weak(String),
+    #[display(fmt = "reset token unknown or expired")]
+    InvalidToken,
+    #[display(fmt = "{_0}")]
+    Query(QueryError),
+}
+
+impl std::error::Error for PasswordError {}
+
+impl From<QueryError> for PasswordError {
+    fn from(err: QueryError) -> Self {
+        PasswordError::Query(err)
+    }
+}
+
+impl From<actix_web::error::BlockingError> for PasswordError {
+    fn from(err: actix_web::error::BlockingError) -> Self {
+        PasswordError::Query(err.into())
+    }
+}
+
+impl PasswordError {
+    /// Short machine readable name of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasswordError::Unauthorized => "unauthorized",
+            PasswordError::Forbidden => "forbidden",
+            PasswordError::NotFound => "not_found",
+            PasswordError::WrongPassword => "wrong_password",
+            PasswordError::Weak(_) => "weak_password",
+            PasswordError::InvalidToken => "invalid_token",
+            PasswordError::Query(err) => err.code(),
+        }
+    }
+}
+
+impl actix_web::ResponseError for PasswordError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            PasswordError::Unauthorized => StatusCode::UNAUTHORIZED,
+            PasswordError::Forbidden | PasswordError::WrongPassword => StatusCode::FORBIDDEN,
+            PasswordError::NotFound => StatusCode::NOT_FOUND,
+            PasswordError::Weak(_) | PasswordError::InvalidToken => StatusCode::BAD_REQUEST,
+            PasswordError::Query(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        actix_web::HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "type": "error",
+            "code": self.code(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+/// Passwords have `min_length` to `PASSWORD_MAX` characters of at least two
+/// kinds out of letters, digits and others, and don't contain the user name
+pub fn check(name: &str, password: &str, min_length: usize) -> Result<(), PasswordError> {
+    let len = password.chars().count();
+    if len < min_length {
+        return Err(PasswordError::Weak(format!("passwords need at least {min_length} characters")));
+    }
+    if len > PASSWORD_MAX {
+        return Err(PasswordError::Weak(format!("passwords may be at most {PASSWORD_MAX} characters")));
+    }
+    let kinds: [fn(char) -> bool; 3] = [char::is_alphabetic, char::is_numeric, |c| !c.is_alphanumeric()];
+    if kinds.iter().filter(|&&kind| password.chars().any(kind)).count() < 2 {
+        return Err(PasswordError::Weak(
+            "passwords need two of letters, digits and other characters".to_owned(),
+        ));
+    }
+    if !name.is_empty() && password.to_lowercase().contains(&name.to_lowercase()) {
+        return Err(PasswordError::Weak("passwords must not contain the user name".to_owned()));
+    }
+    Ok(())
+}
+
+/// Change the password of a user who knows the current one. Returns the
+/// user with its new session stamp.
+pub fn change(
+    store: &dyn ChatStore,
+    config: &Config,
+    user_id: &str,
+    current: &str,
+    new: &str,
+) -> Result<User, PasswordError> {
+    let user = store.query_user_from_id(user_id)?.ok_or(PasswordError::NotFound)?;
+    if user.password != digest(current) {
+        return Err(PasswordError::WrongPassword);
+    }
+    check(&user.name, new, config.min_password_length)?;
+    store.update_user_password(user_id, &digest(new))?;
+    // a token handed out for a forgotten password is of no use any more
+    store.delete_password_reset(user_id)?;
+    store.query_user_from_id(user_id)?.ok_or(PasswordError::NotFound)
+}
+
+/// Hand out a reset token for a user, admins only. The token replaces an
+/// earlier one and is returned with its expiry, only its digest is kept.
+pub fn issue_reset(
+    store: &dyn ChatStore,
+    config: &Config,
+    actor: &str,
+    user_id: &str,
+) -> Result<(String, PasswordReset), PasswordError> {
+    let admin = store.query_user_from_id(actor)?.is_some_and(|user| user.permission_id == 1);
+    if !admin {
+        return Err(PasswordError::Forbidden);
+    }
+    store
+        .query_user_from_id(user_id)?
+        .filter(|user| UserState::from_i32(user.state) != UserState::Deleted)
+        .ok_or(PasswordError::NotFound)?;
+
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+    let reset = PasswordReset::from_details(user_id, digest(token.as_str()), config.reset_token_ttl());
+    store.insert_password_reset(&reset)?;
+    let entry = AuditEntry::from_details(actor, "reset_password", Some(user_id.to_owned()), None, None);
+    store.insert_audit(&entry)?;
+    Ok((token, reset))
+}
+
+/// Set a new password with a reset token, which can't be used again.
+/// Returns the user with its new session stamp.
+pub fn redeem(store: &dyn ChatStore, config: &Config, token: &str, new: &str) -> Result<User, PasswordError> {
+    let reset = store
+        .query_password_reset(&digest(token))?
+        .ok_or(PasswordError::InvalidToken)?;
+    if reset.expires <= chrono::Utc::now().naive_utc() {
+        store.delete_password_reset(&reset.user_id)?;
+        return Err(PasswordError::InvalidToken);
+    }
+    let user = store
+        .query_user_from_id(&reset.user_id)?
+        .filter(|user| UserState::from_i32(user.state) != UserState::Deleted)
+        .ok_or(PasswordError::InvalidToken)?;
+    // checked first, a weak password doesn't use up the token
+    check(&user.name, new, config.min_password_length)?;
+    // only one of several requests with the same token gets through
+    if store.delete_password_reset(&user.uuid)? == 0 {
+        return Err(PasswordError::InvalidToken);
+    }
+    store.update_user_password(&user.uuid, &digest(new))?;
+    store.query_user_from_id(&user.uuid)?.ok_or(PasswordError::InvalidToken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_length() {
+        assert!(check("alice", "abc123", 8).is_err());
+        assert!(check("alice", "abcd1234", 8).is_ok());
+        let long = "a1".repeat(PASSWORD_MAX);
+        assert!(check("alice", &long, 8).is_err());
+    }
+
+    #[test]
+    fn check_kinds() {
+        assert!(check("alice", "abcdefgh", 8).is_err());
+        assert!(check("alice", "12345678", 8).is_err());
+        assert!(check("alice", "abcdefg!", 8).is_ok());
+        assert!(check("alice", "1234567!", 8).is_ok());
+    }
+
+    #[test]
+    fn check_user_name() {
+        assert!(check("alice", "Alice1234", 8).is_err());
+        assert!(check("alice", "xALICEx9", 8).is_err());
+        assert!(check("", "abcd1234", 8).is_ok());
+    }
+}
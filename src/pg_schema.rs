@@ -0,0 +1,222 @@
+// Postgres layout of `schema.rs`: ids are native UUID columns and times
+// are TIMESTAMPTZ.
+
+pub mod sql_types {
+    /// Postgres `UUID`, read and written as the hyphenated `String` the
+    /// models carry
+    #[derive(Debug, Clone, Copy, SqlType, QueryId)]
+    #[postgres(oid = "2950", array_oid = "2951")]
+    pub struct Uuid;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    audit_log (id) {
+        id -> Bigint,
+        actor_id -> Uuid,
+        action -> Varchar,
+        target -> Nullable<Varchar>,
+        room_id -> Nullable<Integer>,
+        reason -> Nullable<Text>,
+        time -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    login_sessions (key_digest) {
+        key_digest -> Varchar,
+        id -> Varchar,
+        user_id -> Nullable<Uuid>,
+        state -> Text,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        created -> Timestamptz,
+        last_seen -> Timestamptz,
+        expires -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    messages (uuid) {
+        uuid -> Uuid,
+        time -> Timestamptz,
+        content -> Text,
+        sender_id -> Uuid,
+        room_id -> Integer,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    password_resets (user_id) {
+        user_id -> Uuid,
+        token -> Varchar,
+        expires -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    recovery_codes (user_id, code) {
+        user_id -> Uuid,
+        code -> Varchar,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    room_bans (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Uuid,
+        moderator_id -> Uuid,
+        reason -> Nullable<Text>,
+        time -> Timestamptz,
+        expires -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    room_invites (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Uuid,
+        inviter_id -> Uuid,
+        time -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    room_members (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Uuid,
+        role -> Integer,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    room_mutes (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Uuid,
+        moderator_id -> Uuid,
+        time -> Timestamptz,
+        expires -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    room_requests (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Uuid,
+        time -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    rooms (id) {
+        id -> Integer,
+        rname -> Varchar,
+        visibility -> Integer,
+        password -> Nullable<Varchar>,
+        archived_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    user_profiles (user_id) {
+        user_id -> Uuid,
+        avatar -> Nullable<Varchar>,
+        bio -> Nullable<Text>,
+        status -> Nullable<Varchar>,
+        timezone -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    user_totp (user_id) {
+        user_id -> Uuid,
+        secret -> Varchar,
+        enabled_at -> Nullable<Timestamptz>,
+        last_step -> Bigint,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::pg_schema::sql_types::Uuid;
+
+    users (uuid) {
+        uuid -> Uuid,
+        name -> Varchar,
+        password -> Varchar,
+        permission_id -> Integer,
+        state -> Integer,
+        deleted_at -> Nullable<Timestamptz>,
+        display_name -> Nullable<Varchar>,
+        session_stamp -> Varchar,
+        display_key -> Nullable<Varchar>,
+        require_2fa -> Bool,
+    }
+}
+
+diesel::joinable!(login_sessions -> users (user_id));
+diesel::joinable!(messages -> rooms (room_id));
+diesel::joinable!(messages -> users (sender_id));
+diesel::joinable!(password_resets -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(room_bans -> rooms (room_id));
+diesel::joinable!(room_invites -> rooms (room_id));
+diesel::joinable!(room_members -> rooms (room_id));
+diesel::joinable!(room_members -> users (user_id));
+diesel::joinable!(room_mutes -> rooms (room_id));
+diesel::joinable!(room_requests -> rooms (room_id));
+diesel::joinable!(room_requests -> users (user_id));
+diesel::joinable!(user_profiles -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    login_sessions,
+    messages,
+    password_resets,
+    recovery_codes,
+    room_bans,
+    room_invites,
+    room_members,
+    room_mutes,
+    room_requests,
+    rooms,
+    user_profiles,
+    user_totp,
+    users,
+);
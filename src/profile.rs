@@ -0,0 +1,293 @@
+//! User profiles: checking profile changes, resizing uploaded avatars and
+//! the view of a profile other users get. The HTTP endpoints are in `api`.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use derive_more::Display;
+use serde::{Deserialize, Deserializer};
+
+use crate::config::Config;
+use crate::events::SystemEvent;
+use crate::models::{self, Profile, User, UserState};
+use crate::store::{ChatStore, QueryError};
+
+/// Url path the avatar directory is served under
+pub const AVATAR_PATH: &str = "/avatars";
+
+/// Uploads wider or taller than this are refused before they are decoded
+const MAX_UPLOAD_PIXELS: u32 = 8192;
+
+/// Error of a profile request
+#[derive(Debug, Display)]
+pub enum ProfileError {
+    #[display(fmt = "log in first")]
+    Unauthorized,
+    #[display(fmt = "only the user or an admin may change a profile")]
+    Forbidden,
+    #[display(fmt = "no such user")]
+    NotFound,
+    #[display(fmt = "{_0}")]
+    Invalid(String),
+    #[display(fmt = "{_0} is taken")]
+    Taken(String),
+    #[display(fmt = "not a usable image: {_0}")]
+    Image(image::ImageError),
+    #[display(fmt = "avatar not saved: {_0}")]
+    Io(std::io::Error),
+    #[display(fmt = "{_0}")]
+    Query(QueryError),
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<QueryError> for ProfileError {
+    fn from(err: QueryError) -> Self {
+        ProfileError::Query(err)
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ProfileError {
+    fn from(err: actix_web::error::BlockingError) -> Self {
+        ProfileError::Query(err.into())
+    }
+}
+
+impl ProfileError {
+    /// Short machine readable name of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProfileError::Unauthorized => "unauthorized",
+            ProfileError::Forbidden => "forbidden",
+            ProfileError::NotFound => "not_found",
+            ProfileError::Invalid(_) => "invalid",
+            ProfileError::Taken(_) => "taken",
+            ProfileError::Image(_) => "invalid_image",
+            ProfileError::Io(_) => "io",
+            ProfileError::Query(err) => err.code(),
+        }
+    }
+}
+
+impl actix_web::ResponseError for ProfileError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            ProfileError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ProfileError::Forbidden => StatusCode::FORBIDDEN,
+            ProfileError::NotFound => StatusCode::NOT_FOUND,
+            ProfileError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ProfileError::Taken(_) => StatusCode::CONFLICT,
+            ProfileError::Image(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ProfileError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ProfileError::Query(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        actix_web::HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "type": "error",
+            "code": self.code(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+/// Changes to a profile. Fields left out stay as they are, `null` or an
+/// empty string clears them.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProfilePatch {
+    #[serde(default, deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub bio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub status: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub timezone: Option<Option<String>>,
+}
+
+/// Tells a field set to `null` apart from one left out
+fn present<'de, D: Deserializer<'de>>(de: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::deserialize(de).map(Some)
+}
+
+/// `models::clean_text`, blank text is no text
+fn clean(text: &str, multiline: bool) -> Option<String> {
+    let text = models::clean_text(text, multiline);
+    (!text.is_empty()).then_some(text)
+}
+
+fn check_length(field: &str, text: &Option<String>, max: usize) -> Result<(), ProfileError> {
+    match text {
+        Some(text) if text.chars().count() > max => {
+            Err(ProfileError::Invalid(format!("{field} may be at most {max} characters")))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether someone else logs in with or is shown under `name`, in any case
+pub fn display_name_taken(store: &dyn ChatStore, user_id: &str, name: &str) -> Result<bool, QueryError> {
+    Ok(store.query_users_named(name)?.iter().any(|user| user.uuid != user_id))
+}
+
+/// A user that is not deleted and their profile, empty if never saved
+pub fn load(store: &dyn ChatStore, user_id: &str) -> Result<(User, Profile), ProfileError> {
+    let user = store
+        .query_user_from_id(user_id)?
+        .filter(|user| UserState::from_i32(user.state) != UserState::Deleted)
+        .ok_or(ProfileError::NotFound)?;
+    let profile = store.query_profile(user_id)?.unwrap_or_else(|| Profile::from_details(user_id));
+    Ok((user, profile))
+}
+
+/// Load the profile of `user_id` for `actor`, who has to be that user or an
+/// admin to change it
+pub fn load_for_change(store: &dyn ChatStore, actor: &str, user_id: &str) -> Result<(User, Profile), ProfileError> {
+    if actor != user_id {
+        let admin = store.query_user_from_id(actor)?.is_some_and(|user| user.permission_id == 1);
+        if !admin {
+            return Err(ProfileError::Forbidden);
+        }
+    }
+    load(store, user_id)
+}
+
+/// Check the changes and save them. Nothing is saved if any of them is
+/// invalid.
+pub fn update(
+    store: &dyn ChatStore,
+    actor: &str,
+    user_id: &str,
+    patch: ProfilePatch,
+) -> Result<(User, Profile), ProfileError> {
+    let (mut user, mut profile) = load_for_change(store, actor, user_id)?;
+
+    let display_name = patch.display_name.map(|name| name.as_deref().and_then(|name| clean(name, false)));
+    if let Some(Some(ref name)) = display_name {
+        if !models::valid_display_name(name) {
+            return Err(ProfileError::Invalid(format!(
+                "display names are 2 to {} ASCII letters, digits, _, - or ., words separated by single spaces",
+                models::DISPLAY_NAME_MAX
+            )));
+        }
+        if display_name_taken(store, user_id, name)? {
+            return Err(ProfileError::Taken(name.clone()));
+        }
+    }
+    if let Some(bio) = patch.bio {
+        profile.bio = bio.as_deref().and_then(|bio| clean(bio, true));
+        check_length("bio", &profile.bio, models::BIO_MAX)?;
+    }
+    if let Some(status) = patch.status {
+        profile.status = status.as_deref().and_then(|status| clean(status, false));
+        check_length("status", &profile.status, models::STATUS_MAX)?;
+    }
+    if let Some(timezone) = patch.timezone {
+        profile.timezone = match timezone.as_deref().map(str::trim).filter(|tz| !tz.is_empty()) {
+            Some(tz) => match tz.parse::<chrono_tz::Tz>() {
+                Ok(tz) => Some(tz.name().to_owned()),
+                Err(_) => return Err(ProfileError::Invalid(format!("unknown time zone {tz}"))),
+            },
+            None => None,
+        };
+    }
+
+    if let Some(name) = display_name {
+        match store.update_user_display_name(user_id, name.as_deref()) {
+            Ok(_) => user.display_name = name,
+            // taken by someone else in the meantime
+            Err(QueryError::Conflict) => return Err(ProfileError::Taken(name.unwrap_or_default())),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    store.update_profile(&profile)?;
+    Ok((user, profile))
+}
+
+/// Save a new avatar for the user or, with `None`, remove it. The old
+/// image is deleted once the profile points at the new one.
+pub fn replace_avatar(
+    store: &dyn ChatStore,
+    config: &Config,
+    actor: &str,
+    user_id: &str,
+    data: Option<&[u8]>,
+) -> Result<(User, Profile), ProfileError> {
+    let (user, mut profile) = load_for_change(store, actor, user_id)?;
+    let avatar = data
+        .map(|data| save_avatar(&config.avatar_dir, data, config.avatar_size))
+        .transpose()?;
+    let old = std::mem::replace(&mut profile.avatar, avatar);
+    if let Err(err) = store.update_profile(&profile) {
+        if let Some(ref name) = profile.avatar {
+            remove_avatar(&config.avatar_dir, name);
+        }
+        return Err(err.into());
+    }
+    if let Some(old) = old {
+        remove_avatar(&config.avatar_dir, &old);
+    }
+    Ok((user, profile))
+}
+
+/// Decode an uploaded image, crop it to a square of `size` pixels and save
+/// it as PNG in `dir`. Returns the file name.
+fn save_avatar(dir: &Path, data: &[u8], size: u32) -> Result<String, ProfileError> {
+    let mut reader = image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(ProfileError::Io)?;
+    let mut limits = image::io::Limits::default();
+    limits.max_image_width = Some(MAX_UPLOAD_PIXELS);
+    limits.max_image_height = Some(MAX_UPLOAD_PIXELS);
+    reader.limits(limits);
+    let image = reader.decode().map_err(ProfileError::Image)?;
+
+    let avatar = image.resize_to_fill(size, size, image::imageops::FilterType::Lanczos3);
+    let mut png = Vec::new();
+    avatar
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .map_err(ProfileError::Image)?;
+    // a new name for every upload, so clients never see a cached old one
+    let name = format!("{}.png", uuid::Uuid::new_v4());
+    std::fs::write(dir.join(&name), png).map_err(ProfileError::Io)?;
+    Ok(name)
+}
+
+/// Remove an avatar that is no longer used, failures are only logged
+fn remove_avatar(dir: &Path, name: &str) {
+    if let Err(err) = std::fs::remove_file(dir.join(name)) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("avatar {name} not removed: {err}");
+        }
+    }
+}
+
+fn avatar_url(profile: &Profile) -> Option<String> {
+    profile.avatar.as_ref().map(|name| format!("{AVATAR_PATH}/{name}"))
+}
+
+/// The profile as every logged in user may see it
+pub fn public(user: &User, profile: &Profile) -> serde_json::Value {
+    serde_json::json!({
+        "id": user.uuid,
+        "username": user.name,
+        "name": user.shown_name(),
+        "display_name": user.display_name,
+        "avatar": avatar_url(profile),
+        "bio": profile.bio,
+        "status": profile.status,
+        "timezone": profile.timezone,
+    })
+}
+
+/// Event telling the rooms the user is in how they are shown now
+pub fn changed(user: &User, profile: &Profile) -> SystemEvent {
+    SystemEvent::ProfileChanged {
+        id: user.uuid.clone(),
+        name: user.shown_name().to_owned(),
+        avatar: avatar_url(profile),
+        status: profile.status.clone(),
+    }
+}
@@ -1,5 +1,31 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Bigint,
+        actor_id -> Char,
+        action -> Varchar,
+        target -> Nullable<Varchar>,
+        room_id -> Nullable<Integer>,
+        reason -> Nullable<Text>,
+        time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_sessions (key_digest) {
+        key_digest -> Char,
+        id -> Varchar,
+        user_id -> Nullable<Char>,
+        state -> Text,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        created -> Timestamp,
+        last_seen -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
 diesel::table! {
     messages (uuid) {
         uuid -> Char,
@@ -10,10 +36,93 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    password_resets (user_id) {
+        user_id -> Char,
+        token -> Char,
+        expires -> Timestamp,
+    }
+}
+
+diesel::table! {
+    recovery_codes (user_id, code) {
+        user_id -> Char,
+        code -> Char,
+    }
+}
+
+diesel::table! {
+    room_bans (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Char,
+        moderator_id -> Char,
+        reason -> Nullable<Text>,
+        time -> Timestamp,
+        expires -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    room_invites (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Char,
+        inviter_id -> Char,
+        time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    room_members (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Char,
+        role -> Integer,
+    }
+}
+
+diesel::table! {
+    room_mutes (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Char,
+        moderator_id -> Char,
+        time -> Timestamp,
+        expires -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    room_requests (room_id, user_id) {
+        room_id -> Integer,
+        user_id -> Char,
+        time -> Timestamp,
+    }
+}
+
 diesel::table! {
     rooms (id) {
         id -> Integer,
         rname -> Varchar,
+        visibility -> Integer,
+        password -> Nullable<Varchar>,
+        archived_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_profiles (user_id) {
+        user_id -> Char,
+        avatar -> Nullable<Varchar>,
+        bio -> Nullable<Text>,
+        status -> Nullable<Varchar>,
+        timezone -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    user_totp (user_id) {
+        user_id -> Char,
+        secret -> Varchar,
+        enabled_at -> Nullable<Timestamp>,
+        last_step -> Bigint,
     }
 }
 
@@ -23,14 +132,43 @@ diesel::table! {
         name -> Varchar,
         password -> Varchar,
         permission_id -> Integer,
+        state -> Integer,
+        deleted_at -> Nullable<Timestamp>,
+        display_name -> Nullable<Varchar>,
+        session_stamp -> Varchar,
+        display_key -> Nullable<Varchar>,
+        require_2fa -> Bool,
     }
 }
 
+diesel::joinable!(login_sessions -> users (user_id));
 diesel::joinable!(messages -> rooms (room_id));
 diesel::joinable!(messages -> users (sender_id));
+diesel::joinable!(password_resets -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(room_bans -> rooms (room_id));
+diesel::joinable!(room_invites -> rooms (room_id));
+diesel::joinable!(room_members -> rooms (room_id));
+diesel::joinable!(room_members -> users (user_id));
+diesel::joinable!(room_mutes -> rooms (room_id));
+diesel::joinable!(room_requests -> rooms (room_id));
+diesel::joinable!(room_requests -> users (user_id));
+diesel::joinable!(user_profiles -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    login_sessions,
     messages,
+    password_resets,
+    recovery_codes,
+    room_bans,
+    room_invites,
+    room_members,
+    room_mutes,
+    room_requests,
     rooms,
+    user_profiles,
+    user_totp,
     users,
 );
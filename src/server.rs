@@ -1,30 +1,62 @@
 //! `ChatServer` is an actor. It maintains list of connection client session.
 //! And manages available rooms. Peers send messages to other peers in same
-//! room through `ChatServer`.
+//! room through the `RoomShard` owning the room, `ChatServer` hands it to
+//! them when they join. Shards run on threads of their own, so a busy room
+//! doesn't hold up delivery in the others. Every instance of the server runs one,
+//! they exchange messages and room changes through a `Broker`.
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use actix::prelude::*;
+use actix_broker::{BrokerIssue, BrokerSubscribe};
 
+use crate::broker::{Broker, Envelope, Event};
+use crate::events::SystemEvent;
 
 /// Chat server sends this messages to session
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Message(pub String);
 
+/// Chat server tells a session it was removed from its room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kicked {
+    /// Room the session was removed from
+    pub room: String,
+    /// Why the session was removed
+    pub reason: String,
+    /// Shard of the default room the session is back in
+    pub shard: Addr<RoomShard>,
+}
+
+/// Chat server tells a session the server is going down, the session
+/// closes its websocket
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Restarting {
+    /// When clients should try to reconnect
+    pub reconnect_in: Duration,
+}
+
 /// Message for chat server communications
 
-/// New chat session is created
+/// New chat session is created, answered with the shard of the default
+/// room
 #[derive(Message)]
-#[rtype(usize)]
+#[rtype(result = "Addr<RoomShard>")]
 pub struct Connect {
     pub addr: Recipient<Message>,
+    pub kicked: Recipient<Kicked>,
+    pub restarting: Recipient<Restarting>,
     pub id: String,
 }
 
@@ -35,7 +67,8 @@ pub struct Disconnect {
     pub id: String,
 }
 
-/// Send message to specific room
+/// Send message to specific room, sessions send it to the shard of their
+/// room
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ClientMessage {
@@ -47,60 +80,357 @@ pub struct ClientMessage {
     pub room: String,
 }
 
-/// List of available rooms
-pub struct ListRooms;
+/// List of available rooms, private rooms are only listed to their members
+pub struct ListRooms {
+    /// Rooms the requesting user is a member of
+    pub member_of: HashSet<String>,
+}
 
 impl actix::Message for ListRooms {
     type Result = Vec<String>;
 }
 
-/// Join room, if room does not exists create new one.
+/// Join room, if room does not exists create new one. Answered with the
+/// shard of the room.
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Addr<RoomShard>")]
 pub struct Join {
     /// Client ID
     pub id: String,
 
     /// Room name
     pub name: String,
+
+    /// Room is private and must not be listed to non members
+    pub hidden: bool,
+}
+
+/// Server is shutting down, every session is told to close and sessions
+/// connecting afterwards right away. Returns the number of sessions told.
+#[derive(Message)]
+#[rtype(usize)]
+pub struct Shutdown {
+    /// When clients should try to reconnect
+    pub reconnect_in: Duration,
+}
+
+/// Number of connected sessions
+#[derive(Message)]
+#[rtype(usize)]
+pub struct SessionCount;
+
+/// Stop the server, its room shards and the arbiters they run on
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Stop;
+
+/// Room visibility changed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetHidden {
+    /// Room name
+    pub name: String,
+
+    /// Room is private and must not be listed to non members
+    pub hidden: bool,
+}
+
+/// Remove a session from a room right away and put it back into the
+/// default room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kick {
+    /// Client ID
+    pub id: String,
+
+    /// Room name
+    pub room: String,
+
+    /// Told to the kicked session
+    pub reason: String,
+}
+
+/// Put a session of this node into a room of the shard
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Enter {
+    room: String,
+    id: String,
+    addr: Recipient<Message>,
+}
+
+/// Take a session of this node out of a room of the shard
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Leave {
+    room: String,
+    id: String,
+}
+
+/// Text for every session in `room` except `skip_id`, on every node
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Broadcast {
+    room: String,
+    msg: String,
+    skip_id: String,
+}
+
+/// Text for the sessions of this node in `room` except `skip_id`
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Deliver {
+    room: String,
+    msg: String,
+    skip_id: String,
+}
+
+/// Delivers the messages of the rooms hashed to it. Only knows the sessions
+/// of this node that are in one of its rooms.
+pub struct RoomShard {
+    rooms: HashMap<String, HashMap<String, Recipient<Message>>>,
+    node: String,
+    broker: Arc<dyn Broker>,
+}
+
+impl RoomShard {
+    /// Send message to the users of this node in the room
+    fn deliver(&self, room: &str, message: &str, skip_id: &str) {
+        if let Some(sessions) = self.rooms.get(room) {
+            for (id, addr) in sessions {
+                if id != skip_id {
+                    addr.do_send(Message(message.to_owned()));
+                }
+            }
+        }
+    }
+}
+
+impl Actor for RoomShard {
+    type Context = Context<Self>;
+}
+
+impl Handler<Enter> for RoomShard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Enter, _: &mut Context<Self>) {
+        self.rooms.entry(msg.room).or_default().insert(msg.id, msg.addr);
+    }
+}
+
+impl Handler<Leave> for RoomShard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
+        if let Some(sessions) = self.rooms.get_mut(&msg.room) {
+            sessions.remove(&msg.id);
+            if sessions.is_empty() {
+                self.rooms.remove(&msg.room);
+            }
+        }
+    }
+}
+
+impl RoomShard {
+    /// Send message to all users in the room, on every node
+    fn broadcast(&self, room: String, msg: String, skip_id: String) {
+        self.deliver(&room, &msg, &skip_id);
+        self.broker.publish(&Envelope {
+            node: self.node.clone(),
+            event: Event::Message { room, msg, skip_id },
+        });
+    }
+}
+
+impl Handler<Broadcast> for RoomShard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Broadcast, _: &mut Context<Self>) {
+        self.broadcast(msg.room, msg.msg, msg.skip_id);
+    }
+}
+
+/// Handler for Message message.
+impl Handler<ClientMessage> for RoomShard {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
+        // sessions that were kicked out of the room can't post to it anymore
+        let joined = self.rooms.get(&msg.room).is_some_and(|sessions| sessions.contains_key(&msg.id));
+        if joined {
+            self.broadcast(msg.room, msg.msg, msg.id);
+        }
+    }
+}
+
+impl Handler<Deliver> for RoomShard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deliver, _: &mut Context<Self>) {
+        self.deliver(&msg.room, &msg.msg, &msg.skip_id);
+    }
 }
 
 /// `ChatServer` manages chat rooms and responsible for coordinating chat session.
 ///
-/// Implementation is very naïve.
+/// Implementation is very naïve. Sessions and their rooms are only known
+/// to the node they are connected to, rooms are known everywhere. The
+/// server only keeps track of who is where, delivering to the members of
+/// a room is left to its shard.
 pub struct ChatServer {
     sessions: HashMap<String, Recipient<Message>>,
-    rooms: HashMap<String, HashSet<String>>,
+    kicked: HashMap<String, Recipient<Kicked>>,
+    restarting: HashMap<String, Recipient<Restarting>>,
+    /// Room each session is in
+    joined: HashMap<String, String>,
+    rooms: HashSet<String>,
+    hidden: HashSet<String>,
+    shards: Vec<Addr<RoomShard>>,
+    /// Arbiters of the shards, stopped with the server
+    arbiters: Vec<ArbiterHandle>,
     visitor_count: Arc<AtomicUsize>,
+    /// Room sessions start in and are kicked back to
+    default_room: String,
+    /// Set once the server is shutting down, to when clients should reconnect
+    shutting_down: Option<Duration>,
+    /// Tells this node's events apart from the ones of other nodes
+    node: String,
+    broker: Arc<dyn Broker>,
 }
 
 impl ChatServer {
-    pub fn new(visitor_count: Arc<AtomicUsize>) -> ChatServer {
+    /// Start `shards` room shards, each on an arbiter of its own
+    pub fn new(
+        visitor_count: Arc<AtomicUsize>,
+        default_room: String,
+        broker: Arc<dyn Broker>,
+        shards: usize,
+    ) -> ChatServer {
         // default room
-        let mut rooms = HashMap::new();
-        rooms.insert("main".to_owned(), HashSet::new());
+        let mut rooms = HashSet::new();
+        rooms.insert(default_room.clone());
+
+        let node = uuid::Uuid::new_v4().to_string();
+        let arbiters: Vec<ArbiterHandle> = (0..shards.max(1)).map(|_| Arbiter::new().handle()).collect();
+        let shards = arbiters
+            .iter()
+            .map(|arbiter| {
+                let node = node.clone();
+                let broker = broker.clone();
+                RoomShard::start_in_arbiter(arbiter, move |_| RoomShard {
+                    rooms: HashMap::new(),
+                    node,
+                    broker,
+                })
+            })
+            .collect();
 
         ChatServer {
             sessions: HashMap::new(),
+            kicked: HashMap::new(),
+            restarting: HashMap::new(),
+            joined: HashMap::new(),
             rooms,
+            hidden: HashSet::new(),
+            shards,
+            arbiters,
             visitor_count,
+            default_room,
+            shutting_down: None,
+            node,
+            broker,
         }
     }
 }
 
 impl ChatServer {
-    /// Send message to all users in the room
-    fn send_message(&self, room: &str, message: &str, skip_id: &String) {
-        if let Some(sessions) = self.rooms.get(room) {
-            for id in sessions {
-                if id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        addr.do_send(Message(message.to_owned()));
-                    }
-                }
+    /// Shard owning the room
+    fn shard(&self, room: &str) -> &Addr<RoomShard> {
+        let mut hasher = DefaultHasher::new();
+        room.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Send message to all users in the room, on every node
+    fn send_message(&self, room: &str, message: &str, skip_id: &str) {
+        self.shard(room).do_send(Broadcast {
+            room: room.to_owned(),
+            msg: message.to_owned(),
+            skip_id: skip_id.to_owned(),
+        });
+    }
+
+    /// Act on an event of the bus, whichever node it was issued on
+    fn on_event(&self, event: &SystemEvent) {
+        if let SystemEvent::ProfileChanged { id, name, avatar, status } = event {
+            // the node the user is connected to tells the room, the event
+            // reaches members on other nodes as a chat message
+            if let Some(room) = self.joined.get(id) {
+                let event = serde_json::json!({
+                    "type": "profile",
+                    "user": { "id": id, "name": name, "avatar": avatar, "status": status },
+                });
+                self.send_message(room, &event.to_string(), "");
             }
         }
     }
+
+    fn publish(&self, event: Event) {
+        self.broker.publish(&Envelope {
+            node: self.node.clone(),
+            event,
+        });
+    }
+
+    /// Put a session into a room, it has to be out of its previous one
+    fn enter(&mut self, id: &str, room: &str) {
+        if let Some(addr) = self.sessions.get(id) {
+            self.shard(room).do_send(Enter {
+                room: room.to_owned(),
+                id: id.to_owned(),
+                addr: addr.clone(),
+            });
+        }
+        self.joined.insert(id.to_owned(), room.to_owned());
+    }
+
+    /// Take a session out of its room, returns the room it was in
+    fn leave(&mut self, id: &str) -> Option<String> {
+        let room = self.joined.remove(id)?;
+        self.shard(&room).do_send(Leave {
+            room: room.clone(),
+            id: id.to_owned(),
+        });
+        Some(room)
+    }
+
+    /// Remember a room and whether it is hidden, returns whether that changed
+    fn open_room(&mut self, name: &str, hidden: bool) -> bool {
+        let opened = self.rooms.insert(name.to_owned());
+        let changed = if hidden {
+            self.hidden.insert(name.to_owned())
+        } else {
+            self.hidden.remove(name)
+        };
+        opened || changed
+    }
+
+    /// Move a session of this node out of a room and back into the default
+    /// room, returns whether it was in there
+    fn kick(&mut self, id: String, room: String, reason: String) -> bool {
+        if self.joined.get(&id) != Some(&room) {
+            return false;
+        }
+        self.leave(&id);
+        let default_room = self.default_room.clone();
+        self.enter(&id, &default_room);
+
+        if let Some(addr) = self.kicked.get(&id) {
+            let shard = self.shard(&default_room).clone();
+            addr.do_send(Kicked { room, reason, shard });
+        }
+        true
+    }
 }
 
 /// Make actor from `ChatServer`
@@ -108,34 +438,48 @@ impl Actor for ChatServer {
     /// We are going to use simple Context, we just need ability to communicate
     /// with other actors.
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.broker.subscribe(ctx.address().recipient());
+        self.subscribe_system_async::<SystemEvent>(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        for arbiter in &self.arbiters {
+            arbiter.stop();
+        }
+    }
 }
 
 /// Handler for Connect message.
 ///
 /// Register new session and assign unique id to this session
 impl Handler<Connect> for ChatServer {
-    type Result = usize;
+    type Result = MessageResult<Connect>;
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
-        println!("Someone joined");
+        log::debug!("[{}]:connected", msg.id);
 
         // notify all users in same room
-        self.send_message("main", "Someone joined", &msg.id);
+        self.send_message(&self.default_room, "Someone joined", &msg.id);
 
         // register session with random id
         self.sessions.insert(msg.id.clone(), msg.addr);
+        self.kicked.insert(msg.id.clone(), msg.kicked);
+        if let Some(reconnect_in) = self.shutting_down {
+            msg.restarting.do_send(Restarting { reconnect_in });
+        }
+        self.restarting.insert(msg.id.clone(), msg.restarting);
 
-        // auto join session to main room
-        self.rooms
-            .entry("main".to_owned())
-            .or_insert_with(HashSet::new)
-            .insert(msg.id.clone());
+        // auto join session to the default room
+        let default_room = self.default_room.clone();
+        self.enter(&msg.id, &default_room);
 
         let count = self.visitor_count.fetch_add(1, Ordering::SeqCst);
-        self.send_message("main", &format!("Total visitors {count}"), &msg.id);
+        self.send_message(&self.default_room, &format!("Total visitors {count}"), &msg.id);
 
-        // send id back
-        1
+        // send the shard of the default room back
+        MessageResult(self.shard(&default_room).clone())
     }
 }
 
@@ -144,32 +488,17 @@ impl Handler<Disconnect> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        println!("Someone disconnected");
-
-        let mut rooms: Vec<String> = Vec::new();
+        log::debug!("[{}]:disconnected", msg.id);
 
         // remove address
+        self.kicked.remove(&msg.id);
+        self.restarting.remove(&msg.id);
         if self.sessions.remove(&msg.id).is_some() {
-            // remove session from all rooms
-            for (name, sessions) in &mut self.rooms {
-                if sessions.remove(&msg.id) {
-                    rooms.push(name.to_owned());
-                }
+            // send message to other users
+            if let Some(room) = self.leave(&msg.id) {
+                self.send_message(&room, "Someone disconnected", &msg.id);
             }
         }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, "Someone disconnected", &msg.id);
-        }
-    }
-}
-
-/// Handler for Message message.
-impl Handler<ClientMessage> for ChatServer {
-    type Result = ();
-
-    fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
-        self.send_message(&msg.room, msg.msg.as_str(), &msg.id);
     }
 }
 
@@ -177,11 +506,13 @@ impl Handler<ClientMessage> for ChatServer {
 impl Handler<ListRooms> for ChatServer {
     type Result = MessageResult<ListRooms>;
 
-    fn handle(&mut self, _: ListRooms, _: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: ListRooms, _: &mut Context<Self>) -> Self::Result {
         let mut rooms = Vec::new();
 
-        for key in self.rooms.keys() {
-            rooms.push(key.to_owned())
+        for key in &self.rooms {
+            if !self.hidden.contains(key) || msg.member_of.contains(key) {
+                rooms.push(key.to_owned())
+            }
         }
 
         MessageResult(rooms)
@@ -191,28 +522,121 @@ impl Handler<ListRooms> for ChatServer {
 /// Join room, send disconnect message to old room
 /// send join message to new room
 impl Handler<Join> for ChatServer {
-    type Result = ();
+    type Result = MessageResult<Join>;
 
-    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
-        let Join { id, name } = msg;
-        let mut rooms = Vec::new();
+    fn handle(&mut self, msg: Join, _: &mut Context<Self>) -> Self::Result {
+        let Join { id, name, hidden } = msg;
 
-        // remove session from all rooms
-        for (n, sessions) in &mut self.rooms {
-            if sessions.remove(&id) {
-                rooms.push(n.to_owned());
-            }
-        }
         // send message to other users
-        for room in rooms {
+        if let Some(room) = self.leave(&id) {
             self.send_message(&room, "Someone disconnected", &id);
         }
 
-        self.rooms
-            .entry(name.clone())
-            .or_insert_with(HashSet::new)
-            .insert(id.clone());
+        if self.open_room(&name, hidden) {
+            self.publish(Event::Room {
+                name: name.clone(),
+                hidden,
+            });
+        }
+        self.enter(&id, &name);
 
         self.send_message(&name, "Someone connected", &id);
+        MessageResult(self.shard(&name).clone())
+    }
+}
+/// Handler for `SetHidden` message.
+impl Handler<SetHidden> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetHidden, _: &mut Context<Self>) {
+        if self.open_room(&msg.name, msg.hidden) {
+            self.publish(Event::Room {
+                name: msg.name,
+                hidden: msg.hidden,
+            });
+        }
+    }
+}
+
+/// Handler for `Kick` message.
+impl Handler<Kick> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Kick, _: &mut Context<Self>) {
+        let Kick { id, room, reason } = msg;
+        // the session may be connected to another node
+        if !self.kick(id.clone(), room.clone(), reason.clone()) {
+            self.publish(Event::Kick { id, room, reason });
+        }
+    }
+}
+
+/// Handler for events published by any node, this one's are skipped
+impl Handler<Envelope> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Envelope, _: &mut Context<Self>) {
+        if msg.node == self.node {
+            return;
+        }
+        match msg.event {
+            Event::Message { room, msg, skip_id } => {
+                self.shard(&room).do_send(Deliver { room, msg, skip_id });
+            }
+            Event::Room { name, hidden } => {
+                self.open_room(&name, hidden);
+            }
+            Event::Kick { id, room, reason } => {
+                self.kick(id, room, reason);
+            }
+            // issued as the server, so it isn't relayed back
+            Event::System { event } => {
+                self.on_event(&event);
+                self.issue_system_async(event);
+            }
+        }
+    }
+}
+
+/// Events of this node's bus are relayed to the other nodes
+impl Handler<SystemEvent> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SystemEvent, _: &mut Context<Self>) {
+        self.on_event(&msg);
+        self.publish(Event::System { event: msg });
+    }
+}
+
+/// Handler for `Shutdown` message.
+impl Handler<Shutdown> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Shutdown, _: &mut Context<Self>) -> Self::Result {
+        self.shutting_down = Some(msg.reconnect_in);
+        for addr in self.restarting.values() {
+            addr.do_send(Restarting {
+                reconnect_in: msg.reconnect_in,
+            });
+        }
+        self.restarting.len()
+    }
+}
+
+/// Handler for `Stop` message.
+impl Handler<Stop> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _: Stop, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
+
+/// Handler for `SessionCount` message.
+impl Handler<SessionCount> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, _: SessionCount, _: &mut Context<Self>) -> Self::Result {
+        self.sessions.len()
     }
 }
@@ -1,44 +1,59 @@
-use crate::query;
+use crate::command::{Command, Reply};
+use crate::config::Config;
+use crate::events::{self, SystemEvent};
+use crate::models;
 use crate::server;
-use crate::models::Pool;
+use crate::store::{ChatStore, QueryError};
+use crate::throttle::{Bucket, RateLimits, Throttle};
 use actix::prelude::*;
+use actix_broker::BrokerSubscribe;
 use actix_web::web;
 use actix_web_actors::ws;
 use std::time::{Duration, Instant};
 
-/// How often heartbeat pings are sent
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-
-/// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
-
 pub struct WsChatSession {
     /// unique session id
     pub id: String,
-    /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
+    /// login session the websocket was opened from, see `login_session`
+    pub login: Option<String>,
+    /// Client must send ping at least once per `client_timeout`,
     /// otherwise we drop connection.
     pub hb: Instant,
 
     /// joined room
     pub room: String,
 
-    /// peer name
-    pub name: Option<String>,
-
     /// Chat server
     pub addr: Addr<server::ChatServer>,
 
-    pub db_pool: web::Data<Pool>,
+    /// Shard of the joined room, chat messages go straight to it
+    pub shard: Option<Addr<server::RoomShard>>,
+
+    pub store: web::Data<dyn ChatStore>,
+
+    /// Failed room password attempts
+    pub join_throttle: web::Data<Throttle>,
+
+    pub config: web::Data<Config>,
+
+    /// Rate limits shared with the other sessions
+    pub rate_limits: web::Data<RateLimits>,
+    /// Chat messages of this session
+    pub messages: Bucket,
+    /// Slash commands of this session
+    pub commands: Bucket,
+    /// Chat messages in a row refused for flooding
+    pub strikes: u32,
 }
 
 impl WsChatSession {
-    /// helper method that sends ping to client every 5 seconds (HEARTBEAT_INTERVAL).
+    /// helper method that sends ping to client every `heartbeat_interval`.
     ///
     /// also this method checks heartbeats from client
     fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+        ctx.run_interval(self.config.heartbeat_interval(), |act, ctx| {
             // check client heartbeats
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+            if Instant::now().duration_since(act.hb) > act.config.client_timeout() {
                 // heartbeat timed out
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
@@ -55,6 +70,159 @@ impl WsChatSession {
             ctx.ping(b"");
         });
     }
+
+    /// Tell the client a query failed, the session keeps running
+    fn report(&self, err: &QueryError, ctx: &mut ws::WebsocketContext<Self>) {
+        log::error!("[{}]:{err}", self.id);
+        let event = serde_json::json!({
+            "type": "error",
+            "code": err.code(),
+            "message": err.to_string(),
+        });
+        ctx.text(event.to_string());
+    }
+
+    /// Whether the rate limits let a frame through. Refused frames get a
+    /// slow down error, users who keep flooding are muted in the room.
+    fn admit(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        let limits = &self.config.limits;
+        let (refused, flooding) = if text.starts_with('/') {
+            (self.commands.take(&limits.commands).err(), false)
+        } else if let Err(wait) = self
+            .messages
+            .take(&limits.session_messages)
+            .and_then(|()| self.rate_limits.user_messages.take(&self.id))
+        {
+            (Some(wait), true)
+        } else {
+            // a busy room slows everyone down, it isn't held against them
+            (self.rate_limits.room_messages.take(&self.room).err(), false)
+        };
+
+        let Some(wait) = refused else {
+            if !text.starts_with('/') {
+                self.strikes = 0;
+            }
+            return true;
+        };
+        self.slow_down(wait, ctx);
+        if flooding {
+            self.strikes += 1;
+            if self.strikes >= limits.flood_strikes && limits.flood_mute > 0 {
+                self.strikes = 0;
+                let duration = limits.flood_mute();
+                self.run_blocking(ctx, move |cmd| cmd.flood_mute(duration));
+            }
+        }
+        false
+    }
+
+    /// Tell the client a frame was refused, the session keeps running
+    fn refuse(&self, code: &str, message: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let event = serde_json::json!({
+            "type": "error",
+            "code": code,
+            "message": message,
+        });
+        ctx.text(event.to_string());
+    }
+
+    fn slow_down(&self, wait: Duration, ctx: &mut ws::WebsocketContext<Self>) {
+        let secs = wait.as_secs_f64();
+        let event = serde_json::json!({
+            "type": "error",
+            "code": "rate_limited",
+            "message": format!("slow down, try again in {secs:.1} seconds"),
+            "retry_after": secs,
+        });
+        ctx.text(event.to_string());
+    }
+
+    /// Run `f` against a snapshot of the session on the blocking thread
+    /// pool and apply its replies afterwards. The context waits for it, so
+    /// frames of one session are still handled in order.
+    fn run_blocking<F>(&mut self, ctx: &mut ws::WebsocketContext<Self>, f: F)
+    where
+        F: FnOnce(&mut Command) -> Result<(), QueryError> + Send + 'static,
+    {
+        let mut cmd = Command {
+            id: self.id.clone(),
+            room: self.room.clone(),
+            addr: self.addr.clone(),
+            shard: self.shard.clone(),
+            store: self.store.clone(),
+            join_throttle: self.join_throttle.clone(),
+            config: self.config.clone(),
+            replies: Vec::new(),
+        };
+        web::block(move || {
+            let res = f(&mut cmd);
+            (cmd, res)
+        })
+        .into_actor(self)
+        .map(|res, act, ctx| match res {
+            Ok((cmd, res)) => {
+                act.apply(cmd, ctx);
+                if let Err(err) = res {
+                    act.report(&err, ctx);
+                }
+            }
+            Err(err) => act.report(&err.into(), ctx),
+        })
+        .wait(ctx);
+    }
+
+    /// Take over the state a command left behind and send its replies
+    fn apply(&mut self, cmd: Command, ctx: &mut ws::WebsocketContext<Self>) {
+        self.room = cmd.room;
+        for reply in cmd.replies {
+            match reply {
+                Reply::Text(text) => ctx.text(text),
+                Reply::ListRooms(member_of) => {
+                    // Send ListRooms message to chat server and wait for
+                    // response
+                    self.addr
+                        .send(server::ListRooms { member_of })
+                        .into_actor(self)
+                        .then(|res, _, ctx| {
+                            match res {
+                                Ok(rooms) => {
+                                    for room in rooms {
+                                        ctx.text(room);
+                                    }
+                                }
+                                _ => println!("Something is wrong"),
+                            }
+                            fut::ready(())
+                        })
+                        .wait(ctx)
+                    // .wait(ctx) pauses all events in context,
+                    // so actor wont receive any new messages until it get list
+                    // of rooms back
+                }
+                Reply::Join { name, hidden } => {
+                    // messages go to the shard of the new room from now on
+                    self.addr
+                        .send(server::Join {
+                            id: self.id.clone(),
+                            name,
+                            hidden,
+                        })
+                        .into_actor(self)
+                        .then(|res, act, _| {
+                            act.shard = res.ok();
+                            fut::ready(())
+                        })
+                        .wait(ctx)
+                }
+                Reply::Event(event) => events::issue(event),
+                Reply::Close => {
+                    ctx.close(None);
+                    ctx.stop();
+                }
+            }
+        }
+    }
 }
 
 impl Actor for WsChatSession {
@@ -65,26 +233,28 @@ impl Actor for WsChatSession {
     fn started(&mut self, ctx: &mut Self::Context) {
         // we'll start heartbeat process on session start.
         self.hb(ctx);
+        self.subscribe_system_async::<SystemEvent>(ctx);
 
         // register self in chat server. `AsyncContext::wait` register
         // future within context, but context waits until this future resolves
         // before processing any other events.
         // HttpContext::state() is instance of WsChatSessionState, state is shared
         // across all routes within application
-        if let Some(db_room) = query::query_room(&"main".to_string(), self.db_pool.clone()) {
-            log::info!("{} exit", db_room.rname);
-        } else {
-            query::insert_room(&"main".to_string(), self.db_pool.clone()).expect("Fail to insert to room");
-        }
+        self.run_blocking(ctx, |cmd| cmd.ensure_default_room());
 
         let addr = ctx.address();
         self.addr
             .send(server::Connect {
-                addr: addr.recipient(),
+                addr: addr.clone().recipient(),
+                kicked: addr.clone().recipient(),
+                restarting: addr.recipient(),
                 id: self.id.clone(),
             })
             .into_actor(self)
-            .then(|res, act, ctx| fut::ready(()))
+            .then(|res, act, _| {
+                act.shard = res.ok();
+                fut::ready(())
+            })
             .wait(ctx);
     }
 
@@ -106,10 +276,96 @@ impl Handler<server::Message> for WsChatSession {
     }
 }
 
+/// Chat server removed the session from its room, it is back in the
+/// default room now
+impl Handler<server::Kicked> for WsChatSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Kicked, ctx: &mut Self::Context) {
+        if self.room == msg.room {
+            self.room = self.config.default_room.clone();
+            self.shard = Some(msg.shard);
+        }
+        ctx.text(format!("you were removed from {}: {}", msg.room, msg.reason));
+    }
+}
+
+/// Events of the event bus. Sessions of a deleted or suspended account, or
+/// of one whose password changed, are closed right away, as are those of a
+/// login session that ended.
+impl Handler<SystemEvent> for WsChatSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: SystemEvent, ctx: &mut Self::Context) {
+        let reason = match msg {
+            SystemEvent::UserDeleted { id } if id == self.id => "your account was deleted",
+            SystemEvent::UserSuspended { id } if id == self.id => "your account was suspended",
+            SystemEvent::PasswordChanged { id } if id == self.id => "your password was changed, log in again",
+            SystemEvent::SessionRevoked { id, session }
+                if id == self.id && self.login.as_deref() == Some(session.as_str()) =>
+            {
+                "you were logged out"
+            }
+            SystemEvent::RoleChanged { id, room, role } if id == self.id => {
+                let role = match role {
+                    models::ROLE_OWNER => "owner",
+                    models::ROLE_MODERATOR => "moderator",
+                    _ => "member",
+                };
+                ctx.text(format!("you are now {role} of {room}"));
+                return;
+            }
+            _ => return,
+        };
+        let event = serde_json::json!({
+            "type": "error",
+            "code": "forbidden",
+            "message": reason,
+        });
+        ctx.text(event.to_string());
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(reason.to_owned()),
+        }));
+        ctx.stop();
+    }
+}
+
+/// Server is going down. Commands still running were waited for before
+/// this is handled, so their writes are done, only the socket is left.
+impl Handler<server::Restarting> for WsChatSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Restarting, ctx: &mut Self::Context) {
+        let secs = msg.reconnect_in.as_secs();
+        let event = serde_json::json!({
+            "type": "shutdown",
+            "message": format!("server restarting, reconnect in {secs} seconds"),
+            "reconnect_in": secs,
+        });
+        ctx.text(event.to_string());
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: Some("server restarting".to_owned()),
+        }));
+        ctx.stop();
+    }
+}
+
 /// WebSocket message handler
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         let msg = match msg {
+            Err(ws::ProtocolError::Overflow) => {
+                let limit = self.config.max_frame_size;
+                self.refuse("too_large", &format!("frames may be at most {limit} bytes"), ctx);
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Size,
+                    description: Some("frame too large".to_owned()),
+                }));
+                ctx.stop();
+                return;
+            }
             Err(_) => {
                 ctx.stop();
                 return;
@@ -127,102 +383,20 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                 self.hb = Instant::now();
             }
             ws::Message::Text(text) => {
-                let m = text.trim();
-                // we check for /sss type of messages
-                if m.starts_with('/') {
-                    let v: Vec<&str> = m.splitn(2, ' ').collect();
-                    match v[0] {
-                        "/list" => {
-                            // Send ListRooms message to chat server and wait for
-                            // response
-                            println!("List rooms");
-                            self.addr
-                                .send(server::ListRooms)
-                                .into_actor(self)
-                                .then(|res, _, ctx| {
-                                    match res {
-                                        Ok(rooms) => {
-                                            for room in rooms {
-                                                ctx.text(room);
-                                            }
-                                        }
-                                        _ => println!("Something is wrong"),
-                                    }
-                                    fut::ready(())
-                                })
-                                .wait(ctx)
-                            // .wait(ctx) pauses all events in context,
-                            // so actor wont receive any new messages until it get list
-                            // of rooms back
-                        }
-                        "/join" => {
-                            if v.len() == 2 {
-                                self.room = v[1].to_owned();
-                                if let Some(db_room) = query::query_room(&self.room, self.db_pool.clone())
-                                {
-                                    log::info!("{} exist", db_room.rname);
-                                } else {
-                                    query::insert_room(&self.room, self.db_pool.clone())
-                                        .expect("Fail to insert value");
-                                }
-                                self.addr.do_send(server::Join {
-                                    id: self.id.clone(),
-                                    name: self.room.clone(),
-                                });
-
-                                ctx.text("joined");
-                            } else {
-                                ctx.text("!!! room name is required");
-                            }
-                        }
-                        "/name" => {
-                            if v.len() == 2 {
-                                self.name = Some(v[1].to_owned());
-                            } else {
-                                ctx.text("!!! name is required");
-                            }
-                        }
-                        "/history" => {
-                            if let Some(now_room) = query::query_room(&self.room, self.db_pool.clone()) {
-                                for i in query::query_message(now_room.id, self.db_pool.clone()) {
-                                    if let Some(value) = query::query_user_from_id(&i.sender_id, self.db_pool.clone()) {
-                                        ctx.text(value.name+":"+&i.content)
-                                    }
-                                    
-                                }
-                            }
-                        }
-                        "/rm" => {
-                            if v.len() == 2 {
-                                if let Some(src_peo) = query::query_user_from_id(&self.id, self.db_pool.clone()) {
-                                    if src_peo.permission_id == 1 {
-                                       query::delete_user(&v[1].to_string(), self.db_pool.clone()).expect("Faile to delete user");
-                                    }
-                                }
-                            } else {
-                                ctx.text("!!! name is required");
-                            }
-
-                        }
-                        _ => ctx.text(format!("!!! unknown command: {m:?}")),
-                    }
-                } else {
-                    let msg = if let Some(ref name) = self.name {
-                        format!("{name}: {m}")
-                    } else {
-                        m.to_owned()
-                    };
-                    if let Some(now_room) = query::query_room(&self.room, self.db_pool.clone()) {
-                        query::insert_message(&msg, now_room.id, &self.id, self.db_pool.clone())
-                            .expect("Fail to insert to msg");
-                    }
-                    // send message to chat server
-                    self.addr.do_send(server::ClientMessage {
-                        id: self.id.clone(),
-                        msg,
-                        room: self.room.clone(),
-                    })
+                let text = models::clean_text(&text, true);
+                if text.is_empty() {
+                    self.refuse("empty", "empty messages are not sent", ctx);
+                    return;
+                }
+                let limit = self.config.max_message_length;
+                if text.chars().count() > limit {
+                    self.refuse("too_long", &format!("messages may be at most {limit} characters"), ctx);
+                    return;
+                }
+                if !self.admit(&text, ctx) {
+                    return;
                 }
+                self.run_blocking(ctx, move |cmd| cmd.handle_text(&text));
             }
             ws::Message::Binary(_) => println!("Unexpected binary"),
             ws::Message::Close(reason) => {
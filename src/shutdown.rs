@@ -0,0 +1,59 @@
+//! Coordinated shutdown on SIGTERM or Ctrl-C: stop accepting connections,
+//! tell every session to reconnect later, wait for the sessions to close
+//! and only then stop the HTTP workers.
+
+use std::time::{Duration, Instant};
+
+use actix::Addr;
+use actix_web::{dev::ServerHandle, rt};
+
+use crate::server;
+
+/// How often the chat server is asked whether sessions are left
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use futures::future::{select, FutureExt};
+        use rt::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                select(rt::signal::ctrl_c().boxed(), Box::pin(term.recv())).await;
+            }
+            Err(err) => {
+                log::warn!("can't listen for SIGTERM: {err}");
+                let _ = rt::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = rt::signal::ctrl_c().await;
+}
+
+/// Shut the server down once a signal arrives. Sessions are given until
+/// `deadline` to close, clients are told to reconnect after `reconnect_in`.
+pub fn spawn(handle: ServerHandle, chat: Addr<server::ChatServer>, reconnect_in: Duration, deadline: Duration) {
+    rt::spawn(async move {
+        signal().await;
+        let start = Instant::now();
+        handle.pause().await;
+
+        let sessions = chat.send(server::Shutdown { reconnect_in }).await.unwrap_or(0);
+        log::info!("shutting down, closing {sessions} sessions");
+        while start.elapsed() < deadline {
+            match chat.send(server::SessionCount).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => rt::time::sleep(DRAIN_POLL).await,
+            }
+        }
+        if let Ok(left) = chat.send(server::SessionCount).await {
+            if left > 0 {
+                log::warn!("{left} sessions still open at the shutdown deadline");
+            }
+        }
+
+        handle.stop(true).await;
+    });
+}
@@ -0,0 +1,514 @@
+//! In-memory store for tests and throwaway instances, behaves like the SQL
+//! stores but nothing survives a restart.
+
+use std::sync::Mutex;
+
+use super::{AuditFilter, ChatStore, QueryError};
+use crate::models::{
+    self, AuditEntry, JoinRequest, LoginSession, Mess, PasswordReset, Profile, RecoveryCode, Room, RoomBan, RoomInvite,
+    RoomMember, RoomMute, Totp, User, UserState,
+};
+
+#[derive(Default)]
+struct Tables {
+    users: Vec<User>,
+    profiles: Vec<Profile>,
+    resets: Vec<PasswordReset>,
+    totp: Vec<Totp>,
+    recovery_codes: Vec<RecoveryCode>,
+    login_sessions: Vec<LoginSession>,
+    rooms: Vec<Room>,
+    messages: Vec<Mess>,
+    members: Vec<RoomMember>,
+    invites: Vec<RoomInvite>,
+    requests: Vec<JoinRequest>,
+    bans: Vec<RoomBan>,
+    mutes: Vec<RoomMute>,
+    audit: Vec<AuditEntry>,
+    next_room_id: i32,
+    next_audit_id: i64,
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    fn tables(&self) -> std::sync::MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap()
+    }
+}
+
+/// Remove every row matching `f`, returning how many were removed
+fn remove_where<T>(rows: &mut Vec<T>, f: impl Fn(&T) -> bool) -> usize {
+    let before = rows.len();
+    rows.retain(|row| !f(row));
+    before - rows.len()
+}
+
+/// Insert `row`, replacing the one with the same key, like `REPLACE INTO`
+fn replace<T>(rows: &mut Vec<T>, row: T, same_key: impl Fn(&T) -> bool) -> usize {
+    let replaced = remove_where(rows, same_key);
+    rows.push(row);
+    replaced + 1
+}
+
+fn active(expires: Option<chrono::NaiveDateTime>) -> bool {
+    expires.is_none_or(|expires| expires > chrono::Utc::now().naive_utc())
+}
+
+impl ChatStore for MemoryStore {
+    fn query_user(&self, user: &str) -> Result<Option<User>, QueryError> {
+        Ok(self.tables().users.iter().find(|u| u.name == user).cloned())
+    }
+    fn query_user_from_id(&self, user_id: &str) -> Result<Option<User>, QueryError> {
+        Ok(self.tables().users.iter().find(|u| u.uuid == user_id).cloned())
+    }
+    fn delete_user(&self, user: &str) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        let Some(uuid) = tables.users.iter().find(|u| u.name == user).map(|u| u.uuid.clone()) else {
+            return Ok(0);
+        };
+        remove_where(&mut tables.members, |m| m.user_id == uuid);
+        remove_where(&mut tables.invites, |i| i.user_id == uuid || i.inviter_id == uuid);
+        remove_where(&mut tables.requests, |r| r.user_id == uuid);
+        remove_where(&mut tables.bans, |b| b.user_id == uuid);
+        remove_where(&mut tables.mutes, |m| m.user_id == uuid);
+        remove_where(&mut tables.profiles, |p| p.user_id == uuid);
+        remove_where(&mut tables.resets, |r| r.user_id == uuid);
+        remove_where(&mut tables.totp, |t| t.user_id == uuid);
+        remove_where(&mut tables.recovery_codes, |c| c.user_id == uuid);
+        remove_where(&mut tables.login_sessions, |s| s.user_id.as_deref() == Some(uuid.as_str()));
+        let now = chrono::Utc::now().naive_utc();
+        let mut count = 0;
+        for u in tables.users.iter_mut().filter(|u| u.uuid == uuid) {
+            u.state = UserState::Deleted.as_i32();
+            u.deleted_at = Some(now);
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn update_user_state(&self, user_id: &str, state: UserState) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for u in self.tables().users.iter_mut() {
+            if u.uuid == user_id && u.state != UserState::Deleted.as_i32() {
+                u.state = state.as_i32();
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    fn erase_user(&self, user_id: &str) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        let deleted = |u: &User| {
+            u.uuid == user_id && u.state == UserState::Deleted.as_i32() && u.uuid != models::DELETED_USER_ID
+        };
+        if !tables.users.iter().any(deleted) {
+            return Ok(0);
+        }
+        for m in tables.messages.iter_mut().filter(|m| m.sender_id == user_id) {
+            m.sender_id = models::DELETED_USER_ID.to_owned();
+        }
+        for b in tables.bans.iter_mut().filter(|b| b.moderator_id == user_id) {
+            b.moderator_id = models::DELETED_USER_ID.to_owned();
+        }
+        for m in tables.mutes.iter_mut().filter(|m| m.moderator_id == user_id) {
+            m.moderator_id = models::DELETED_USER_ID.to_owned();
+        }
+        remove_where(&mut tables.profiles, |p| p.user_id == user_id);
+        remove_where(&mut tables.resets, |r| r.user_id == user_id);
+        remove_where(&mut tables.totp, |t| t.user_id == user_id);
+        remove_where(&mut tables.recovery_codes, |c| c.user_id == user_id);
+        remove_where(&mut tables.login_sessions, |s| s.user_id.as_deref() == Some(user_id));
+        Ok(remove_where(&mut tables.users, deleted))
+    }
+    fn query_erasable_users(&self, before: chrono::NaiveDateTime) -> Result<Vec<String>, QueryError> {
+        Ok(self
+            .tables()
+            .users
+            .iter()
+            .filter(|u| u.state == UserState::Deleted.as_i32())
+            .filter(|u| u.deleted_at.is_some_and(|at| at < before))
+            .filter(|u| u.uuid != models::DELETED_USER_ID)
+            .map(|u| u.uuid.clone())
+            .collect())
+    }
+    fn query_users(&self) -> Result<Vec<User>, QueryError> {
+        let mut users = self.tables().users.clone();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(users)
+    }
+    fn update_user_permission(&self, user_id: &str, permission_id: i32) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for u in self.tables().users.iter_mut().filter(|u| u.uuid == user_id) {
+            u.permission_id = permission_id;
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn update_user_password(&self, user_id: &str, password: &str) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for u in self.tables().users.iter_mut().filter(|u| u.uuid == user_id) {
+            u.password = password.to_owned();
+            u.session_stamp = uuid::Uuid::new_v4().to_string();
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn insert_user(&self, user: &str, pass: &str) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        if tables.users.iter().any(|u| u.name == user) {
+            return Err(QueryError::Conflict);
+        }
+        tables.users.push(User::from_details(user, pass));
+        Ok(1)
+    }
+    fn query_users_named(&self, name: &str) -> Result<Vec<User>, QueryError> {
+        let key = models::name_key(name);
+        Ok(self
+            .tables()
+            .users
+            .iter()
+            .filter(|u| models::name_key(&u.name) == key || u.display_key.as_ref() == Some(&key))
+            .cloned()
+            .collect())
+    }
+    fn update_user_display_name(&self, user_id: &str, display_name: Option<&str>) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        let display_key = display_name.map(models::name_key);
+        if display_key.is_some() && tables.users.iter().any(|u| u.uuid != user_id && u.display_key == display_key) {
+            return Err(QueryError::Conflict);
+        }
+        let mut count = 0;
+        for u in tables.users.iter_mut().filter(|u| u.uuid == user_id) {
+            u.display_name = display_name.map(str::to_owned);
+            u.display_key = display_key.clone();
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn update_user_require_2fa(&self, user_id: &str, required: bool) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for u in self.tables().users.iter_mut().filter(|u| u.uuid == user_id) {
+            u.require_2fa = required;
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn query_profile(&self, user_id: &str) -> Result<Option<Profile>, QueryError> {
+        Ok(self.tables().profiles.iter().find(|p| p.user_id == user_id).cloned())
+    }
+    fn update_profile(&self, profile: &Profile) -> Result<usize, QueryError> {
+        Ok(replace(&mut self.tables().profiles, profile.clone(), |p| {
+            p.user_id == profile.user_id
+        }))
+    }
+    fn insert_password_reset(&self, reset: &PasswordReset) -> Result<usize, QueryError> {
+        Ok(replace(&mut self.tables().resets, reset.clone(), |r| r.user_id == reset.user_id))
+    }
+    fn query_password_reset(&self, token: &str) -> Result<Option<PasswordReset>, QueryError> {
+        Ok(self.tables().resets.iter().find(|r| r.token == token).cloned())
+    }
+    fn delete_password_reset(&self, user_id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().resets, |r| r.user_id == user_id))
+    }
+    fn query_totp(&self, user_id: &str) -> Result<Option<Totp>, QueryError> {
+        Ok(self.tables().totp.iter().find(|t| t.user_id == user_id).cloned())
+    }
+    fn update_totp(&self, totp: &Totp) -> Result<usize, QueryError> {
+        Ok(replace(&mut self.tables().totp, totp.clone(), |t| t.user_id == totp.user_id))
+    }
+    fn update_totp_step(&self, user_id: &str, step: i64) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for t in self.tables().totp.iter_mut() {
+            if t.user_id == user_id && t.last_step < step {
+                t.last_step = step;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    fn delete_totp(&self, user_id: &str) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        remove_where(&mut tables.recovery_codes, |c| c.user_id == user_id);
+        Ok(remove_where(&mut tables.totp, |t| t.user_id == user_id))
+    }
+    fn replace_recovery_codes(&self, codes: &[RecoveryCode]) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        for code in codes {
+            remove_where(&mut tables.recovery_codes, |c| c.user_id == code.user_id);
+        }
+        tables.recovery_codes.extend_from_slice(codes);
+        Ok(codes.len())
+    }
+    fn delete_recovery_code(&self, user_id: &str, code: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().recovery_codes, |c| {
+            c.user_id == user_id && c.code == code
+        }))
+    }
+    fn query_login_session(&self, key_digest: &str) -> Result<Option<LoginSession>, QueryError> {
+        Ok(self.tables().login_sessions.iter().find(|s| s.key_digest == key_digest).cloned())
+    }
+    fn update_login_session(&self, session: &LoginSession) -> Result<usize, QueryError> {
+        Ok(replace(&mut self.tables().login_sessions, session.clone(), |s| {
+            s.key_digest == session.key_digest
+        }))
+    }
+    fn touch_login_session(&self, key_digest: &str, last_seen: chrono::NaiveDateTime) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for s in self.tables().login_sessions.iter_mut().filter(|s| s.key_digest == key_digest) {
+            s.last_seen = last_seen;
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn delete_login_session(&self, key_digest: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().login_sessions, |s| s.key_digest == key_digest))
+    }
+    fn query_login_sessions(&self, user_id: &str) -> Result<Vec<LoginSession>, QueryError> {
+        let mut sessions: Vec<LoginSession> = self
+            .tables()
+            .login_sessions
+            .iter()
+            .filter(|s| s.user_id.as_deref() == Some(user_id))
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+        Ok(sessions)
+    }
+    fn revoke_login_session(&self, user_id: &str, id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().login_sessions, |s| {
+            s.user_id.as_deref() == Some(user_id) && s.id == id
+        }))
+    }
+    fn delete_login_sessions(&self, user_id: &str, keep: Option<&str>) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().login_sessions, |s| {
+            s.user_id.as_deref() == Some(user_id) && Some(s.id.as_str()) != keep
+        }))
+    }
+    fn delete_stale_login_sessions(
+        &self,
+        now: chrono::NaiveDateTime,
+        idle_since: chrono::NaiveDateTime,
+    ) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().login_sessions, |s| {
+            s.expires <= now || s.last_seen < idle_since
+        }))
+    }
+
+    fn query_room(&self, ro_name: &str) -> Result<Option<Room>, QueryError> {
+        Ok(self.tables().rooms.iter().find(|r| r.rname == ro_name).cloned())
+    }
+    fn query_rooms(&self) -> Result<Vec<Room>, QueryError> {
+        let mut rooms = self.tables().rooms.clone();
+        rooms.sort_by(|a, b| a.rname.cmp(&b.rname));
+        Ok(rooms)
+    }
+    fn insert_room(&self, ro_name: &str) -> Result<(), QueryError> {
+        let mut tables = self.tables();
+        if tables.rooms.iter().any(|r| r.rname == ro_name) {
+            return Err(QueryError::Conflict);
+        }
+        tables.next_room_id += 1;
+        let mut room = Room::from_details(ro_name);
+        room.id = tables.next_room_id;
+        tables.rooms.push(room);
+        Ok(())
+    }
+    fn update_room_visibility(&self, room_id: i32, visibility: i32) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for r in self.tables().rooms.iter_mut().filter(|r| r.id == room_id) {
+            r.visibility = visibility;
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn update_room_password(&self, room_id: i32, password: Option<String>) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for r in self.tables().rooms.iter_mut().filter(|r| r.id == room_id) {
+            r.password = password.clone();
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn update_room_archived(
+        &self,
+        room_id: i32,
+        archived_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<usize, QueryError> {
+        let mut count = 0;
+        for r in self.tables().rooms.iter_mut().filter(|r| r.id == room_id) {
+            r.archived_at = archived_at;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn query_message(&self, room_id: i32) -> Result<Vec<Mess>, QueryError> {
+        Ok(self.tables().messages.iter().filter(|m| m.room_id == room_id).cloned().collect())
+    }
+    fn insert_message(&self, msg: &str, room_id: i32, sender_id: &str) -> Result<(), QueryError> {
+        self.tables().messages.push(Mess::from_details(sender_id, msg, room_id));
+        Ok(())
+    }
+
+    fn query_member(&self, room_id: i32, user_id: &str) -> Result<Option<RoomMember>, QueryError> {
+        Ok(self
+            .tables()
+            .members
+            .iter()
+            .find(|m| m.room_id == room_id && m.user_id == user_id)
+            .cloned())
+    }
+    fn insert_member(&self, room_id: i32, user_id: &str, role: i32) -> Result<usize, QueryError> {
+        let member = RoomMember {
+            room_id,
+            user_id: user_id.to_owned(),
+            role,
+        };
+        Ok(replace(&mut self.tables().members, member, |m| {
+            m.room_id == room_id && m.user_id == user_id
+        }))
+    }
+    fn delete_member(&self, room_id: i32, user_id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().members, |m| {
+            m.room_id == room_id && m.user_id == user_id
+        }))
+    }
+    fn query_member_rooms(&self, user_id: &str) -> Result<Vec<String>, QueryError> {
+        let tables = self.tables();
+        Ok(tables
+            .members
+            .iter()
+            .filter(|m| m.user_id == user_id)
+            .filter_map(|m| tables.rooms.iter().find(|r| r.id == m.room_id))
+            .map(|r| r.rname.clone())
+            .collect())
+    }
+    fn query_memberships(&self, user_id: &str) -> Result<Vec<RoomMember>, QueryError> {
+        Ok(self.tables().members.iter().filter(|m| m.user_id == user_id).cloned().collect())
+    }
+
+    fn insert_invite(&self, room_id: i32, user_id: &str, inviter_id: &str) -> Result<usize, QueryError> {
+        let invite = RoomInvite::from_details(room_id, user_id, inviter_id);
+        Ok(replace(&mut self.tables().invites, invite, |i| {
+            i.room_id == room_id && i.user_id == user_id
+        }))
+    }
+    fn query_invites(&self, user_id: &str) -> Result<Vec<(RoomInvite, Room)>, QueryError> {
+        let tables = self.tables();
+        Ok(tables
+            .invites
+            .iter()
+            .filter(|i| i.user_id == user_id)
+            .filter_map(|i| {
+                let room = tables.rooms.iter().find(|r| r.id == i.room_id)?;
+                Some((i.clone(), room.clone()))
+            })
+            .collect())
+    }
+    fn delete_invite(&self, room_id: i32, user_id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().invites, |i| {
+            i.room_id == room_id && i.user_id == user_id
+        }))
+    }
+
+    fn insert_request(&self, room_id: i32, user_id: &str) -> Result<usize, QueryError> {
+        let request = JoinRequest::from_details(room_id, user_id);
+        Ok(replace(&mut self.tables().requests, request, |r| {
+            r.room_id == room_id && r.user_id == user_id
+        }))
+    }
+    fn query_requests(&self, room_id: i32) -> Result<Vec<(JoinRequest, User)>, QueryError> {
+        let tables = self.tables();
+        Ok(tables
+            .requests
+            .iter()
+            .filter(|r| r.room_id == room_id)
+            .filter_map(|r| {
+                let user = tables.users.iter().find(|u| u.uuid == r.user_id)?;
+                Some((r.clone(), user.clone()))
+            })
+            .collect())
+    }
+    fn delete_request(&self, room_id: i32, user_id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().requests, |r| {
+            r.room_id == room_id && r.user_id == user_id
+        }))
+    }
+
+    fn insert_ban(&self, ban: &RoomBan) -> Result<usize, QueryError> {
+        Ok(replace(&mut self.tables().bans, ban.clone(), |b| {
+            b.room_id == ban.room_id && b.user_id == ban.user_id
+        }))
+    }
+    fn query_ban(&self, room_id: i32, user_id: &str) -> Result<Option<RoomBan>, QueryError> {
+        Ok(self
+            .tables()
+            .bans
+            .iter()
+            .find(|b| b.room_id == room_id && b.user_id == user_id && active(b.expires))
+            .cloned())
+    }
+    fn delete_ban(&self, room_id: i32, user_id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().bans, |b| {
+            b.room_id == room_id && b.user_id == user_id
+        }))
+    }
+
+    fn insert_mute(&self, mute: &RoomMute) -> Result<usize, QueryError> {
+        Ok(replace(&mut self.tables().mutes, mute.clone(), |m| {
+            m.room_id == mute.room_id && m.user_id == mute.user_id
+        }))
+    }
+    fn query_mute(&self, room_id: i32, user_id: &str) -> Result<Option<RoomMute>, QueryError> {
+        Ok(self
+            .tables()
+            .mutes
+            .iter()
+            .find(|m| m.room_id == room_id && m.user_id == user_id && active(m.expires))
+            .cloned())
+    }
+    fn delete_mute(&self, room_id: i32, user_id: &str) -> Result<usize, QueryError> {
+        Ok(remove_where(&mut self.tables().mutes, |m| {
+            m.room_id == room_id && m.user_id == user_id
+        }))
+    }
+
+    fn insert_audit(&self, entry: &AuditEntry) -> Result<usize, QueryError> {
+        let mut tables = self.tables();
+        tables.next_audit_id += 1;
+        let mut entry = entry.clone();
+        entry.id = tables.next_audit_id;
+        tables.audit.push(entry);
+        Ok(1)
+    }
+    fn query_audit(
+        &self,
+        filter: &AuditFilter,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<AuditEntry>, i64), QueryError> {
+        let tables = self.tables();
+        let matching: Vec<&AuditEntry> = tables.audit.iter().rev().filter(|e| filter.matches(e)).collect();
+        let items = matching
+            .iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|e| (*e).clone())
+            .collect();
+
+        Ok((items, matching.len() as i64))
+    }
+
+    fn migrations(&self) -> Result<Vec<(&'static str, bool)>, QueryError> {
+        Ok(Vec::new())
+    }
+    fn migrate_up(&self) -> Result<Vec<&'static str>, QueryError> {
+        Ok(Vec::new())
+    }
+    fn migrate_down(&self) -> Result<Option<&'static str>, QueryError> {
+        Ok(None)
+    }
+}
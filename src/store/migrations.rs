@@ -0,0 +1,123 @@
+//! Migrations compiled into the binary, one set per SQL backend. Applied
+//! versions are tracked in `__diesel_schema_migrations` like the diesel
+//! CLI does, so both can be used on the same database.
+
+use diesel_migrations::{setup_database, MigrationConnection};
+
+use super::QueryError;
+
+pub struct Migration {
+    pub version: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+macro_rules! embed {
+    ($backend:literal, $($version:literal => $dir:literal),* $(,)?) => {
+        &[$(Migration {
+            version: $version,
+            up: include_str!(concat!("../../migrations/", $backend, "/", $dir, "/up.sql")),
+            down: include_str!(concat!("../../migrations/", $backend, "/", $dir, "/down.sql")),
+        }),*]
+    };
+}
+
+pub const MYSQL: &[Migration] = embed!(
+    "mysql",
+    "20230529063203" => "2023-05-29-063203_rooms",
+    "20230529063213" => "2023-05-29-063213_users",
+    "20230529063220" => "2023-05-29-063220_messages",
+    "20230605080000" => "2023-06-05-080000_room_access",
+    "20230608090000" => "2023-06-08-090000_room_password",
+    "20230612100000" => "2023-06-12-100000_room_moderation",
+    "20230615110000" => "2023-06-15-110000_audit_log",
+    "20230619090000" => "2023-06-19-090000_user_state",
+    "20230703090000" => "2023-07-03-090000_unique_names_and_indexes",
+    "20230706090000" => "2023-07-06-090000_room_archive",
+    "20230710090000" => "2023-07-10-090000_display_names",
+    "20230713090000" => "2023-07-13-090000_user_profiles",
+    "20230717090000" => "2023-07-17-090000_password_resets",
+    "20230720090000" => "2023-07-20-090000_two_factor",
+    "20230724090000" => "2023-07-24-090000_login_sessions",
+    "20230727090000" => "2023-07-27-090000_display_name_keys",
+    "20230731090000" => "2023-07-31-090000_require_2fa",
+);
+
+pub const SQLITE: &[Migration] = embed!(
+    "sqlite",
+    "20230626090000" => "2023-06-26-090000_create_tables",
+    "20230703090000" => "2023-07-03-090000_unique_names_and_indexes",
+    "20230706090000" => "2023-07-06-090000_room_archive",
+    "20230710090000" => "2023-07-10-090000_display_names",
+    "20230713090000" => "2023-07-13-090000_user_profiles",
+    "20230717090000" => "2023-07-17-090000_password_resets",
+    "20230720090000" => "2023-07-20-090000_two_factor",
+    "20230724090000" => "2023-07-24-090000_login_sessions",
+    "20230727090000" => "2023-07-27-090000_display_name_keys",
+    "20230731090000" => "2023-07-31-090000_require_2fa",
+);
+
+pub const POSTGRES: &[Migration] = embed!(
+    "postgres",
+    "20230629090000" => "2023-06-29-090000_create_tables",
+    "20230703090000" => "2023-07-03-090000_unique_names_and_indexes",
+    "20230706090000" => "2023-07-06-090000_room_archive",
+    "20230710090000" => "2023-07-10-090000_display_names",
+    "20230713090000" => "2023-07-13-090000_user_profiles",
+    "20230717090000" => "2023-07-17-090000_password_resets",
+    "20230720090000" => "2023-07-20-090000_two_factor",
+    "20230724090000" => "2023-07-24-090000_login_sessions",
+    "20230727090000" => "2023-07-27-090000_display_name_keys",
+    "20230731090000" => "2023-07-31-090000_require_2fa",
+);
+
+/// Every migration of the set and whether it was applied
+pub fn status<C: MigrationConnection>(
+    conn: &C,
+    migrations: &[Migration],
+) -> Result<Vec<(&'static str, bool)>, QueryError> {
+    setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+    Ok(migrations
+        .iter()
+        .map(|migration| (migration.version, applied.contains(migration.version)))
+        .collect())
+}
+
+/// Apply the pending migrations in order, each in its own transaction.
+/// Returns the versions that were applied.
+pub fn up<C: MigrationConnection>(conn: &C, migrations: &[Migration]) -> Result<Vec<&'static str>, QueryError> {
+    setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+    let mut done = Vec::new();
+    for migration in migrations.iter().filter(|m| !applied.contains(m.version)) {
+        conn.transaction::<_, QueryError, _>(|| {
+            conn.batch_execute(migration.up)?;
+            conn.insert_new_migration(migration.version)?;
+            Ok(())
+        })?;
+        log::info!("applied migration {}", migration.version);
+        done.push(migration.version);
+    }
+    Ok(done)
+}
+
+/// Revert the latest applied migration, `None` when nothing is applied
+pub fn down<C: MigrationConnection>(conn: &C, migrations: &[Migration]) -> Result<Option<&'static str>, QueryError> {
+    setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+    let Some(migration) = migrations.iter().rev().find(|m| applied.contains(m.version)) else {
+        return Ok(None);
+    };
+    conn.transaction::<_, QueryError, _>(|| {
+        conn.batch_execute(migration.down)?;
+        // versions are the constants above, nothing to escape
+        conn.execute(&format!(
+            "DELETE FROM __diesel_schema_migrations WHERE version = '{}'",
+            migration.version
+        ))?;
+        Ok(())
+    })?;
+    log::info!("reverted migration {}", migration.version);
+    Ok(Some(migration.version))
+}
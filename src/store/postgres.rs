@@ -0,0 +1,55 @@
+//! Conversions between the `String` ids of the models and Postgres `UUID`
+//! columns.
+
+use std::io::Write;
+
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::bound::Bound;
+use diesel::expression::AsExpression;
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Nullable;
+
+use crate::pg_schema::sql_types::Uuid;
+
+impl FromSql<Uuid, Pg> for String {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let bytes = not_none!(bytes);
+        Ok(uuid::Uuid::from_slice(bytes)?.to_string())
+    }
+}
+
+/// An id bound to a `UUID` column, the hyphenated string is parsed when
+/// the query is sent
+#[derive(Debug, Clone, Copy)]
+pub struct PgUuid<'a>(pub &'a str);
+
+impl<'a> ToSql<Uuid, Pg> for PgUuid<'a> {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(uuid::Uuid::parse_str(self.0)?.as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl<'a> AsExpression<Uuid> for PgUuid<'a> {
+    type Expression = Bound<Uuid, Self>;
+
+    fn as_expression(self) -> Self::Expression {
+        Bound::new(self)
+    }
+}
+
+/// Ids bound to nullable `UUID` columns, like the user of a login session
+impl<'a> ToSql<Nullable<Uuid>, Pg> for PgUuid<'a> {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Uuid, Pg>::to_sql(self, out)
+    }
+}
+
+impl<'a> AsExpression<Nullable<Uuid>> for PgUuid<'a> {
+    type Expression = Bound<Nullable<Uuid>, Self>;
+
+    fn as_expression(self) -> Self::Expression {
+        Bound::new(self)
+    }
+}
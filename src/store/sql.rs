@@ -0,0 +1,798 @@
+//! Diesel backed stores. MySQL, SQLite and Postgres share every query,
+//! `sql_store!` implements `ChatStore` once per connection type.
+
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection};
+
+use super::migrations;
+use super::postgres::PgUuid;
+use super::{AuditFilter, ChatStore, QueryError};
+use crate::models::{
+    self, AuditEntry, JoinRequest, LoginSession, Mess, PasswordReset, Profile, RecoveryCode, Room, RoomBan, RoomInvite,
+    RoomMember, RoomMute, Totp, User,
+};
+
+/// requests wait at most this long for a free connection before they are
+/// answered with "service busy"
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+/// `REPLACE INTO` on MySQL and SQLite
+macro_rules! replace_into {
+    ($conn:expr, $table:expr, $values:expr, $keys:expr, $($col:ident),*) => {
+        diesel::replace_into($table).values($values).execute($conn)
+    };
+}
+
+/// Postgres has no `REPLACE INTO`, update the row with the same primary key
+/// instead
+macro_rules! upsert {
+    ($conn:expr, $table:expr, $values:expr, $keys:expr, $($col:ident),*) => {
+        diesel::insert_into($table)
+            .values($values)
+            .on_conflict($keys)
+            .do_update()
+            .set(($($col.eq(diesel::pg::upsert::excluded($col)),)*))
+            .execute($conn)
+    };
+}
+
+/// Implement `ChatStore` for `$store`, a pool of `$conn` connections to a
+/// database laid out like `crate::$schema` by `$migrations`. `$id` turns a user or message
+/// id into a value for the id columns of that schema. Models only derive
+/// `Insertable` for `crate::schema`, so rows are inserted column by column.
+macro_rules! sql_store {
+    ($store:ident, $conn:ty, $schema:ident, $replace:ident, $id:path, $migrations:expr) => {
+        pub struct $store {
+            pool: r2d2::Pool<ConnectionManager<$conn>>,
+        }
+
+        impl ChatStore for $store {
+            fn query_user(&self, user: &str) -> Result<Option<User>, QueryError> {
+                use crate::$schema::users::dsl::{name, users};
+                let conn = &self.pool.get()?;
+                let mut items = users.filter(name.eq(user)).load::<User>(conn)?;
+
+                Ok(items.pop())
+            }
+            fn query_user_from_id(&self, user_id: &str) -> Result<Option<User>, QueryError> {
+                use crate::$schema::users::dsl::{users, uuid};
+                let conn = &self.pool.get()?;
+                let mut items = users.filter(uuid.eq($id(user_id))).load::<User>(conn)?;
+
+                Ok(items.pop())
+            }
+            fn delete_user(&self, user: &str) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{deleted_at, name, state, users};
+                use crate::$schema::{
+                    login_sessions, password_resets, recovery_codes, room_bans, room_invites, room_members, room_mutes,
+                    room_requests, user_profiles, user_totp,
+                };
+                let conn = &self.pool.get()?;
+                conn.transaction::<_, QueryError, _>(|| {
+                    let Some(value) = users.filter(name.eq(user)).first::<User>(conn).optional()? else {
+                        return Ok(0);
+                    };
+                    diesel::delete(room_members::table.filter(room_members::user_id.eq($id(&value.uuid))))
+                        .execute(conn)?;
+                    diesel::delete(
+                        room_invites::table.filter(
+                            room_invites::user_id
+                                .eq($id(&value.uuid))
+                                .or(room_invites::inviter_id.eq($id(&value.uuid))),
+                        ),
+                    )
+                    .execute(conn)?;
+                    diesel::delete(room_requests::table.filter(room_requests::user_id.eq($id(&value.uuid))))
+                        .execute(conn)?;
+                    diesel::delete(room_bans::table.filter(room_bans::user_id.eq($id(&value.uuid)))).execute(conn)?;
+                    diesel::delete(room_mutes::table.filter(room_mutes::user_id.eq($id(&value.uuid)))).execute(conn)?;
+                    diesel::delete(user_profiles::table.find($id(&value.uuid))).execute(conn)?;
+                    diesel::delete(password_resets::table.find($id(&value.uuid))).execute(conn)?;
+                    diesel::delete(user_totp::table.find($id(&value.uuid))).execute(conn)?;
+                    diesel::delete(login_sessions::table.filter(login_sessions::user_id.eq($id(&value.uuid))))
+                        .execute(conn)?;
+                    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq($id(&value.uuid))))
+                        .execute(conn)?;
+                    Ok(diesel::update(users.find($id(&value.uuid)))
+                        .set((
+                            state.eq(models::UserState::Deleted.as_i32()),
+                            deleted_at.eq(chrono::Utc::now().naive_utc()),
+                        ))
+                        .execute(conn)?)
+                })
+            }
+            fn update_user_state(&self, user_id: &str, state_: models::UserState) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{state, users};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(
+                    users
+                        .find($id(user_id))
+                        .filter(state.ne(models::UserState::Deleted.as_i32())),
+                )
+                .set(state.eq(state_.as_i32()))
+                .execute(conn)?)
+            }
+            fn erase_user(&self, user_id: &str) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{state, users};
+                use crate::$schema::{
+                    login_sessions, messages, password_resets, recovery_codes, room_bans, room_mutes, user_profiles,
+                    user_totp,
+                };
+                let conn = &self.pool.get()?;
+                conn.transaction::<_, QueryError, _>(|| {
+                    let deleted = users
+                        .find($id(user_id))
+                        .filter(state.eq(models::UserState::Deleted.as_i32()))
+                        .filter(crate::$schema::users::uuid.ne($id(models::DELETED_USER_ID)));
+                    if deleted.first::<User>(conn).optional()?.is_none() {
+                        return Ok(0);
+                    }
+                    diesel::update(messages::table.filter(messages::sender_id.eq($id(user_id))))
+                        .set(messages::sender_id.eq($id(models::DELETED_USER_ID)))
+                        .execute(conn)?;
+                    diesel::update(room_bans::table.filter(room_bans::moderator_id.eq($id(user_id))))
+                        .set(room_bans::moderator_id.eq($id(models::DELETED_USER_ID)))
+                        .execute(conn)?;
+                    diesel::update(room_mutes::table.filter(room_mutes::moderator_id.eq($id(user_id))))
+                        .set(room_mutes::moderator_id.eq($id(models::DELETED_USER_ID)))
+                        .execute(conn)?;
+                    diesel::delete(user_profiles::table.find($id(user_id))).execute(conn)?;
+                    diesel::delete(password_resets::table.find($id(user_id))).execute(conn)?;
+                    diesel::delete(user_totp::table.find($id(user_id))).execute(conn)?;
+                    diesel::delete(login_sessions::table.filter(login_sessions::user_id.eq($id(user_id)))).execute(conn)?;
+                    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq($id(user_id))))
+                        .execute(conn)?;
+                    Ok(diesel::delete(deleted).execute(conn)?)
+                })
+            }
+            fn query_erasable_users(&self, before: chrono::NaiveDateTime) -> Result<Vec<String>, QueryError> {
+                use crate::$schema::users::dsl::{deleted_at, state, users, uuid};
+                let conn = &self.pool.get()?;
+                Ok(users
+                    .filter(state.eq(models::UserState::Deleted.as_i32()))
+                    .filter(deleted_at.lt(before))
+                    .filter(uuid.ne($id(models::DELETED_USER_ID)))
+                    .select(uuid)
+                    .load::<String>(conn)?)
+            }
+            fn query_users(&self) -> Result<Vec<User>, QueryError> {
+                use crate::$schema::users::dsl::{name, users};
+                let conn = &self.pool.get()?;
+                Ok(users.order(name.asc()).load::<User>(conn)?)
+            }
+            fn update_user_permission(&self, user_id: &str, permission_id_: i32) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{permission_id, users};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(users.find($id(user_id)))
+                    .set(permission_id.eq(permission_id_))
+                    .execute(conn)?)
+            }
+            fn update_user_password(&self, user_id: &str, password_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{password, session_stamp, users};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(users.find($id(user_id)))
+                    .set((
+                        password.eq(password_),
+                        session_stamp.eq(uuid::Uuid::new_v4().to_string()),
+                    ))
+                    .execute(conn)?)
+            }
+            fn insert_user(&self, user: &str, pass: &str) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::*;
+                let conn = &self.pool.get()?;
+                let new_user = models::User::from_details(user, pass);
+
+                Ok(diesel::insert_into(users)
+                    .values((
+                        uuid.eq($id(&new_user.uuid)),
+                        name.eq(&new_user.name),
+                        password.eq(&new_user.password),
+                        permission_id.eq(new_user.permission_id),
+                        state.eq(new_user.state),
+                        deleted_at.eq(new_user.deleted_at),
+                        display_name.eq(&new_user.display_name),
+                        session_stamp.eq(&new_user.session_stamp),
+                        display_key.eq(&new_user.display_key),
+                        require_2fa.eq(new_user.require_2fa),
+                    ))
+                    .execute(conn)?)
+            }
+            fn query_users_named(&self, name_: &str) -> Result<Vec<User>, QueryError> {
+                use crate::$schema::users::dsl::{display_key, name, users};
+                let conn = &self.pool.get()?;
+                let key = models::name_key(name_);
+                Ok(users
+                    .filter(lower(name).eq(&key).or(display_key.eq(&key)))
+                    .load::<User>(conn)?)
+            }
+            fn update_user_display_name(&self, user_id: &str, display_name_: Option<&str>) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{display_key, display_name, users};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(users.find($id(user_id)))
+                    .set((
+                        display_name.eq(display_name_),
+                        display_key.eq(display_name_.map(models::name_key)),
+                    ))
+                    .execute(conn)?)
+            }
+            fn update_user_require_2fa(&self, user_id: &str, required: bool) -> Result<usize, QueryError> {
+                use crate::$schema::users::dsl::{require_2fa, users};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(users.find($id(user_id)))
+                    .set(require_2fa.eq(required))
+                    .execute(conn)?)
+            }
+            fn query_profile(&self, user_id_: &str) -> Result<Option<Profile>, QueryError> {
+                use crate::$schema::user_profiles::dsl::user_profiles;
+                let conn = &self.pool.get()?;
+                Ok(user_profiles.find($id(user_id_)).first::<Profile>(conn).optional()?)
+            }
+            fn update_profile(&self, profile: &Profile) -> Result<usize, QueryError> {
+                use crate::$schema::user_profiles::dsl::{avatar, bio, status, timezone, user_id, user_profiles};
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    user_profiles,
+                    (
+                        user_id.eq($id(&profile.user_id)),
+                        avatar.eq(&profile.avatar),
+                        bio.eq(&profile.bio),
+                        status.eq(&profile.status),
+                        timezone.eq(&profile.timezone),
+                    ),
+                    user_id,
+                    avatar,
+                    bio,
+                    status,
+                    timezone
+                )?)
+            }
+            fn insert_password_reset(&self, reset: &PasswordReset) -> Result<usize, QueryError> {
+                use crate::$schema::password_resets::dsl::{expires, password_resets, token, user_id};
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    password_resets,
+                    (
+                        user_id.eq($id(&reset.user_id)),
+                        token.eq(&reset.token),
+                        expires.eq(reset.expires),
+                    ),
+                    user_id,
+                    token,
+                    expires
+                )?)
+            }
+            fn query_password_reset(&self, token_: &str) -> Result<Option<PasswordReset>, QueryError> {
+                use crate::$schema::password_resets::dsl::{password_resets, token};
+                let conn = &self.pool.get()?;
+                Ok(password_resets.filter(token.eq(token_)).first::<PasswordReset>(conn).optional()?)
+            }
+            fn delete_password_reset(&self, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::password_resets::dsl::password_resets;
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(password_resets.find($id(user_id_))).execute(conn)?)
+            }
+            fn query_totp(&self, user_id_: &str) -> Result<Option<Totp>, QueryError> {
+                use crate::$schema::user_totp::dsl::user_totp;
+                let conn = &self.pool.get()?;
+                Ok(user_totp.find($id(user_id_)).first::<Totp>(conn).optional()?)
+            }
+            fn update_totp(&self, totp: &Totp) -> Result<usize, QueryError> {
+                use crate::$schema::user_totp::dsl::{enabled_at, last_step, secret, user_id, user_totp};
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    user_totp,
+                    (
+                        user_id.eq($id(&totp.user_id)),
+                        secret.eq(&totp.secret),
+                        enabled_at.eq(totp.enabled_at),
+                        last_step.eq(totp.last_step),
+                    ),
+                    user_id,
+                    secret,
+                    enabled_at,
+                    last_step
+                )?)
+            }
+            fn update_totp_step(&self, user_id_: &str, step: i64) -> Result<usize, QueryError> {
+                use crate::$schema::user_totp::dsl::{last_step, user_totp};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(user_totp.find($id(user_id_)).filter(last_step.lt(step)))
+                    .set(last_step.eq(step))
+                    .execute(conn)?)
+            }
+            fn delete_totp(&self, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::{recovery_codes, user_totp};
+                let conn = &self.pool.get()?;
+                conn.transaction::<_, QueryError, _>(|| {
+                    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq($id(user_id_))))
+                        .execute(conn)?;
+                    Ok(diesel::delete(user_totp::table.find($id(user_id_))).execute(conn)?)
+                })
+            }
+            fn replace_recovery_codes(&self, codes: &[RecoveryCode]) -> Result<usize, QueryError> {
+                use crate::$schema::recovery_codes::dsl::{code, recovery_codes, user_id};
+                let conn = &self.pool.get()?;
+                conn.transaction::<_, QueryError, _>(|| {
+                    let mut count = 0;
+                    for row in codes {
+                        if count == 0 {
+                            diesel::delete(recovery_codes.filter(user_id.eq($id(&row.user_id)))).execute(conn)?;
+                        }
+                        count += diesel::insert_into(recovery_codes)
+                            .values((user_id.eq($id(&row.user_id)), code.eq(&row.code)))
+                            .execute(conn)?;
+                    }
+                    Ok(count)
+                })
+            }
+            fn delete_recovery_code(&self, user_id_: &str, code_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::recovery_codes::dsl::{code, recovery_codes, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(recovery_codes.filter(user_id.eq($id(user_id_))).filter(code.eq(code_)))
+                    .execute(conn)?)
+            }
+            fn query_login_session(&self, key_digest_: &str) -> Result<Option<LoginSession>, QueryError> {
+                use crate::$schema::login_sessions::dsl::login_sessions;
+                let conn = &self.pool.get()?;
+                Ok(login_sessions.find(key_digest_).first::<LoginSession>(conn).optional()?)
+            }
+            fn update_login_session(&self, session: &LoginSession) -> Result<usize, QueryError> {
+                use crate::$schema::login_sessions::dsl::{
+                    created, expires, id, ip, key_digest, last_seen, login_sessions, state, user_agent, user_id,
+                };
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    login_sessions,
+                    (
+                        key_digest.eq(&session.key_digest),
+                        id.eq(&session.id),
+                        user_id.eq(session.user_id.as_deref().map($id)),
+                        state.eq(&session.state),
+                        user_agent.eq(&session.user_agent),
+                        ip.eq(&session.ip),
+                        created.eq(session.created),
+                        last_seen.eq(session.last_seen),
+                        expires.eq(session.expires),
+                    ),
+                    key_digest,
+                    id,
+                    user_id,
+                    state,
+                    user_agent,
+                    ip,
+                    created,
+                    last_seen,
+                    expires
+                )?)
+            }
+            fn touch_login_session(&self, key_digest_: &str, last_seen_: chrono::NaiveDateTime) -> Result<usize, QueryError> {
+                use crate::$schema::login_sessions::dsl::{last_seen, login_sessions};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(login_sessions.find(key_digest_))
+                    .set(last_seen.eq(last_seen_))
+                    .execute(conn)?)
+            }
+            fn delete_login_session(&self, key_digest_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::login_sessions::dsl::login_sessions;
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(login_sessions.find(key_digest_)).execute(conn)?)
+            }
+            fn query_login_sessions(&self, user_id_: &str) -> Result<Vec<LoginSession>, QueryError> {
+                use crate::$schema::login_sessions::dsl::{last_seen, login_sessions, user_id};
+                let conn = &self.pool.get()?;
+                Ok(login_sessions
+                    .filter(user_id.eq($id(user_id_)))
+                    .order(last_seen.desc())
+                    .load::<LoginSession>(conn)?)
+            }
+            fn revoke_login_session(&self, user_id_: &str, id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::login_sessions::dsl::{id, login_sessions, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(login_sessions.filter(user_id.eq($id(user_id_))).filter(id.eq(id_))).execute(conn)?)
+            }
+            fn delete_login_sessions(&self, user_id_: &str, keep: Option<&str>) -> Result<usize, QueryError> {
+                use crate::$schema::login_sessions::dsl::{id, login_sessions, user_id};
+                let conn = &self.pool.get()?;
+                let sessions = login_sessions.filter(user_id.eq($id(user_id_)));
+                Ok(match keep {
+                    Some(keep) => diesel::delete(sessions.filter(id.ne(keep))).execute(conn)?,
+                    None => diesel::delete(sessions).execute(conn)?,
+                })
+            }
+            fn delete_stale_login_sessions(
+                &self,
+                now: chrono::NaiveDateTime,
+                idle_since: chrono::NaiveDateTime,
+            ) -> Result<usize, QueryError> {
+                use crate::$schema::login_sessions::dsl::{expires, last_seen, login_sessions};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(login_sessions.filter(expires.le(now).or(last_seen.lt(idle_since)))).execute(conn)?)
+            }
+            fn query_room(&self, ro_name: &str) -> Result<Option<Room>, QueryError> {
+                use crate::$schema::rooms::dsl::{rname, rooms};
+                let conn = &self.pool.get()?;
+                let mut items = rooms.filter(rname.eq(ro_name)).load::<Room>(conn)?;
+
+                Ok(items.pop())
+            }
+            fn query_rooms(&self) -> Result<Vec<Room>, QueryError> {
+                use crate::$schema::rooms::dsl::{rname, rooms};
+                let conn = &self.pool.get()?;
+                Ok(rooms.order(rname.asc()).load::<Room>(conn)?)
+            }
+            fn insert_room(&self, ro_name: &str) -> Result<(), QueryError> {
+                use crate::$schema::rooms::dsl::{password, rname, rooms, visibility};
+                let conn = &self.pool.get()?;
+                // the id is left to the database
+                let new_room = models::Room::from_details(ro_name);
+                diesel::insert_into(rooms)
+                    .values((
+                        rname.eq(&new_room.rname),
+                        visibility.eq(new_room.visibility),
+                        password.eq(&new_room.password),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            }
+            fn update_room_visibility(&self, room_id_: i32, visibility_: i32) -> Result<usize, QueryError> {
+                use crate::$schema::rooms::dsl::{id, rooms, visibility};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(rooms.filter(id.eq(room_id_)))
+                    .set(visibility.eq(visibility_))
+                    .execute(conn)?)
+            }
+            fn update_room_password(&self, room_id_: i32, password_: Option<String>) -> Result<usize, QueryError> {
+                use crate::$schema::rooms::dsl::{id, password, rooms};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(rooms.filter(id.eq(room_id_)))
+                    .set(password.eq(password_))
+                    .execute(conn)?)
+            }
+            fn update_room_archived(
+                &self,
+                room_id_: i32,
+                archived_at_: Option<chrono::NaiveDateTime>,
+            ) -> Result<usize, QueryError> {
+                use crate::$schema::rooms::dsl::{archived_at, id, rooms};
+                let conn = &self.pool.get()?;
+                Ok(diesel::update(rooms.filter(id.eq(room_id_)))
+                    .set(archived_at.eq(archived_at_))
+                    .execute(conn)?)
+            }
+            fn query_message(&self, room_id_: i32) -> Result<Vec<Mess>, QueryError> {
+                use crate::$schema::messages::dsl::{messages, room_id, time};
+                let conn = &self.pool.get()?;
+                let items = messages
+                    .filter(room_id.eq(&room_id_))
+                    .order(time.asc())
+                    .load::<Mess>(conn)?;
+
+                Ok(items)
+            }
+            fn insert_message(&self, msg: &str, room_id_: i32, sender_id_: &str) -> Result<(), QueryError> {
+                use crate::$schema::messages::dsl::*;
+                let conn = &self.pool.get()?;
+                let new_msg = models::Mess::from_details(sender_id_, msg, room_id_);
+                diesel::insert_into(messages)
+                    .values((
+                        uuid.eq($id(&new_msg.uuid)),
+                        time.eq(new_msg.time),
+                        content.eq(&new_msg.content),
+                        sender_id.eq($id(&new_msg.sender_id)),
+                        room_id.eq(new_msg.room_id),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            }
+            fn query_member(&self, room_id_: i32, user_id_: &str) -> Result<Option<RoomMember>, QueryError> {
+                use crate::$schema::room_members::dsl::{room_id, room_members, user_id};
+                let conn = &self.pool.get()?;
+                let mut items = room_members
+                    .filter(room_id.eq(room_id_))
+                    .filter(user_id.eq($id(user_id_)))
+                    .load::<RoomMember>(conn)?;
+
+                Ok(items.pop())
+            }
+            fn insert_member(&self, room_id_: i32, user_id_: &str, role_: i32) -> Result<usize, QueryError> {
+                use crate::$schema::room_members::dsl::*;
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    room_members,
+                    (room_id.eq(room_id_), user_id.eq($id(user_id_)), role.eq(role_)),
+                    (room_id, user_id),
+                    role
+                )?)
+            }
+            fn delete_member(&self, room_id_: i32, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_members::dsl::{room_id, room_members, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(room_members.filter(room_id.eq(room_id_)).filter(user_id.eq($id(user_id_))))
+                    .execute(conn)?)
+            }
+            fn query_member_rooms(&self, user_id_: &str) -> Result<Vec<String>, QueryError> {
+                use crate::$schema::room_members::dsl::{room_members, user_id};
+                use crate::$schema::rooms::dsl::{rname, rooms};
+                let conn = &self.pool.get()?;
+                Ok(room_members
+                    .inner_join(rooms)
+                    .filter(user_id.eq($id(user_id_)))
+                    .select(rname)
+                    .load::<String>(conn)?)
+            }
+            fn query_memberships(&self, user_id_: &str) -> Result<Vec<RoomMember>, QueryError> {
+                use crate::$schema::room_members::dsl::{room_members, user_id};
+                let conn = &self.pool.get()?;
+                Ok(room_members.filter(user_id.eq($id(user_id_))).load::<RoomMember>(conn)?)
+            }
+            fn insert_invite(&self, room_id_: i32, user_id_: &str, inviter_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_invites::dsl::*;
+                let conn = &self.pool.get()?;
+                let invite = RoomInvite::from_details(room_id_, user_id_, inviter_id_);
+                Ok($replace!(
+                    conn,
+                    room_invites,
+                    (
+                        room_id.eq(invite.room_id),
+                        user_id.eq($id(&invite.user_id)),
+                        inviter_id.eq($id(&invite.inviter_id)),
+                        time.eq(invite.time),
+                    ),
+                    (room_id, user_id),
+                    inviter_id,
+                    time
+                )?)
+            }
+            fn query_invites(&self, user_id_: &str) -> Result<Vec<(RoomInvite, Room)>, QueryError> {
+                use crate::$schema::room_invites::dsl::{room_invites, user_id};
+                use crate::$schema::rooms::dsl::rooms;
+                let conn = &self.pool.get()?;
+                Ok(room_invites
+                    .inner_join(rooms)
+                    .filter(user_id.eq($id(user_id_)))
+                    .load::<(RoomInvite, Room)>(conn)?)
+            }
+            fn delete_invite(&self, room_id_: i32, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_invites::dsl::{room_id, room_invites, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(room_invites.filter(room_id.eq(room_id_)).filter(user_id.eq($id(user_id_))))
+                    .execute(conn)?)
+            }
+            fn insert_request(&self, room_id_: i32, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_requests::dsl::*;
+                let conn = &self.pool.get()?;
+                let request = JoinRequest::from_details(room_id_, user_id_);
+                Ok($replace!(
+                    conn,
+                    room_requests,
+                    (
+                        room_id.eq(request.room_id),
+                        user_id.eq($id(&request.user_id)),
+                        time.eq(request.time),
+                    ),
+                    (room_id, user_id),
+                    time
+                )?)
+            }
+            fn query_requests(&self, room_id_: i32) -> Result<Vec<(JoinRequest, User)>, QueryError> {
+                use crate::$schema::room_requests::dsl::{room_id, room_requests};
+                use crate::$schema::users::dsl::users;
+                let conn = &self.pool.get()?;
+                Ok(room_requests
+                    .inner_join(users)
+                    .filter(room_id.eq(room_id_))
+                    .load::<(JoinRequest, User)>(conn)?)
+            }
+            fn delete_request(&self, room_id_: i32, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_requests::dsl::{room_id, room_requests, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(room_requests.filter(room_id.eq(room_id_)).filter(user_id.eq($id(user_id_))))
+                    .execute(conn)?)
+            }
+            fn insert_ban(&self, ban: &RoomBan) -> Result<usize, QueryError> {
+                use crate::$schema::room_bans::dsl::*;
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    room_bans,
+                    (
+                        room_id.eq(ban.room_id),
+                        user_id.eq($id(&ban.user_id)),
+                        moderator_id.eq($id(&ban.moderator_id)),
+                        reason.eq(&ban.reason),
+                        time.eq(ban.time),
+                        expires.eq(ban.expires),
+                    ),
+                    (room_id, user_id),
+                    moderator_id,
+                    reason,
+                    time,
+                    expires
+                )?)
+            }
+            fn query_ban(&self, room_id_: i32, user_id_: &str) -> Result<Option<RoomBan>, QueryError> {
+                use crate::$schema::room_bans::dsl::{expires, room_bans, room_id, user_id};
+                let conn = &self.pool.get()?;
+                let now = chrono::Utc::now().naive_utc();
+                let mut items = room_bans
+                    .filter(room_id.eq(room_id_))
+                    .filter(user_id.eq($id(user_id_)))
+                    .filter(expires.is_null().or(expires.gt(now)))
+                    .load::<RoomBan>(conn)?;
+
+                Ok(items.pop())
+            }
+            fn delete_ban(&self, room_id_: i32, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_bans::dsl::{room_bans, room_id, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(room_bans.filter(room_id.eq(room_id_)).filter(user_id.eq($id(user_id_))))
+                    .execute(conn)?)
+            }
+            fn insert_mute(&self, mute: &RoomMute) -> Result<usize, QueryError> {
+                use crate::$schema::room_mutes::dsl::*;
+                let conn = &self.pool.get()?;
+                Ok($replace!(
+                    conn,
+                    room_mutes,
+                    (
+                        room_id.eq(mute.room_id),
+                        user_id.eq($id(&mute.user_id)),
+                        moderator_id.eq($id(&mute.moderator_id)),
+                        time.eq(mute.time),
+                        expires.eq(mute.expires),
+                    ),
+                    (room_id, user_id),
+                    moderator_id,
+                    time,
+                    expires
+                )?)
+            }
+            fn query_mute(&self, room_id_: i32, user_id_: &str) -> Result<Option<RoomMute>, QueryError> {
+                use crate::$schema::room_mutes::dsl::{expires, room_id, room_mutes, user_id};
+                let conn = &self.pool.get()?;
+                let now = chrono::Utc::now().naive_utc();
+                let mut items = room_mutes
+                    .filter(room_id.eq(room_id_))
+                    .filter(user_id.eq($id(user_id_)))
+                    .filter(expires.is_null().or(expires.gt(now)))
+                    .load::<RoomMute>(conn)?;
+
+                Ok(items.pop())
+            }
+            fn delete_mute(&self, room_id_: i32, user_id_: &str) -> Result<usize, QueryError> {
+                use crate::$schema::room_mutes::dsl::{room_id, room_mutes, user_id};
+                let conn = &self.pool.get()?;
+                Ok(diesel::delete(room_mutes.filter(room_id.eq(room_id_)).filter(user_id.eq($id(user_id_))))
+                    .execute(conn)?)
+            }
+            fn insert_audit(&self, entry: &AuditEntry) -> Result<usize, QueryError> {
+                use crate::$schema::audit_log::dsl::*;
+                let conn = &self.pool.get()?;
+                // the id is left to the database
+                Ok(diesel::insert_into(audit_log)
+                    .values((
+                        actor_id.eq($id(&entry.actor_id)),
+                        action.eq(&entry.action),
+                        target.eq(&entry.target),
+                        room_id.eq(entry.room_id),
+                        reason.eq(&entry.reason),
+                        time.eq(entry.time),
+                    ))
+                    .execute(conn)?)
+            }
+            fn query_audit(
+                &self,
+                filter: &AuditFilter,
+                offset: i64,
+                limit: i64,
+            ) -> Result<(Vec<AuditEntry>, i64), QueryError> {
+                use crate::$schema::audit_log::dsl::*;
+                let conn = &self.pool.get()?;
+                let filtered = || {
+                    let mut items = audit_log.into_boxed();
+                    if let Some(ref actor) = filter.actor {
+                        items = items.filter(actor_id.eq($id(actor)));
+                    }
+                    if let Some(ref act) = filter.action {
+                        items = items.filter(action.eq(act));
+                    }
+                    if let Some(ref tgt) = filter.target {
+                        items = items.filter(target.eq(tgt));
+                    }
+                    if let Some(room) = filter.room {
+                        items = items.filter(room_id.eq(room));
+                    }
+                    if let Some(since) = filter.since {
+                        items = items.filter(time.ge(since));
+                    }
+                    if let Some(until) = filter.until {
+                        items = items.filter(time.lt(until));
+                    }
+                    items
+                };
+                let total = filtered().count().get_result::<i64>(conn)?;
+                let items = filtered()
+                    .order(id.desc())
+                    .offset(offset)
+                    .limit(limit)
+                    .load::<AuditEntry>(conn)?;
+
+                Ok((items, total))
+            }
+            fn migrations(&self) -> Result<Vec<(&'static str, bool)>, QueryError> {
+                migrations::status(&*self.pool.get()?, $migrations)
+            }
+            fn migrate_up(&self) -> Result<Vec<&'static str>, QueryError> {
+                migrations::up(&*self.pool.get()?, $migrations)
+            }
+            fn migrate_down(&self) -> Result<Option<&'static str>, QueryError> {
+                migrations::down(&*self.pool.get()?, $migrations)
+            }
+        }
+    };
+}
+
+/// Ids are stored as text on MySQL and SQLite
+fn text_id<T>(id: T) -> T {
+    id
+}
+
+sql_store!(MysqlStore, MysqlConnection, schema, replace_into, text_id, migrations::MYSQL);
+sql_store!(SqliteStore, SqliteConnection, schema, replace_into, text_id, migrations::SQLITE);
+sql_store!(PgStore, PgConnection, pg_schema, upsert, PgUuid, migrations::POSTGRES);
+
+impl MysqlStore {
+    pub fn connect(database_url: &str) -> Result<Self, String> {
+        let manager = ConnectionManager::<MysqlConnection>::new(database_url);
+        let pool = r2d2::Pool::builder()
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .build(manager)
+            .map_err(|err| format!("failed to create pool: {err}"))?;
+        Ok(MysqlStore { pool })
+    }
+}
+
+/// SQLite leaves foreign keys off and fails right away on a locked
+/// database unless told otherwise, per connection
+#[derive(Debug)]
+struct SqlitePragmas;
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for SqlitePragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        conn.batch_execute("PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000;")
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+impl PgStore {
+    pub fn connect(database_url: &str) -> Result<Self, String> {
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+        let pool = r2d2::Pool::builder()
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .build(manager)
+            .map_err(|err| format!("failed to create pool: {err}"))?;
+        Ok(PgStore { pool })
+    }
+}
+
+impl SqliteStore {
+    pub fn connect(path: &str) -> Result<Self, String> {
+        let manager = ConnectionManager::<SqliteConnection>::new(path);
+        let pool = r2d2::Pool::builder()
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .connection_customizer(Box::new(SqlitePragmas))
+            .build(manager)
+            .map_err(|err| format!("failed to create pool: {err}"))?;
+        Ok(SqliteStore { pool })
+    }
+}
@@ -0,0 +1,209 @@
+//! `Throttle` counts failed attempts per key and locks the key out for a
+//! while once too many of them happened in a row. `Bucket` and
+//! `RateLimiter` limit how often something may happen at all.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::{Limit, Limits};
+
+/// Failed attempts allowed before the key gets locked
+const MAX_FAILURES: u32 = 5;
+
+/// How long a key stays locked
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+/// Keys kept before the idle ones are dropped, they are no different from
+/// a new one. Buckets are pruned the same way.
+const PRUNE_AT: usize = 10_000;
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    /// Locked, or failed within the last `LOCKOUT`
+    fn active(&self, now: Instant) -> bool {
+        self.locked_until.is_some_and(|until| until > now) || now.duration_since(self.last_failure) < LOCKOUT
+    }
+}
+
+#[derive(Default)]
+pub struct Throttle {
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl Throttle {
+    /// Time left until the key may try again, `None` if it is not locked
+    pub fn locked(&self, key: &str) -> Option<Duration> {
+        let attempts = self.attempts.lock().unwrap();
+        attempts
+            .get(key)
+            .and_then(|attempt| attempt.locked_until)
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+    }
+
+    /// Record a failed attempt, locking the key when it failed too often
+    pub fn fail(&self, key: &str) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+        if attempts.len() >= PRUNE_AT {
+            attempts.retain(|_, attempt| attempt.active(now));
+        }
+        let attempt = attempts.entry(key.to_owned()).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        attempt.failures += 1;
+        attempt.last_failure = now;
+        if attempt.failures >= MAX_FAILURES {
+            attempt.failures = 0;
+            attempt.locked_until = Some(now + LOCKOUT);
+        }
+    }
+
+    /// Forget about a key after a successful attempt
+    pub fn reset(&self, key: &str) {
+        self.attempts.lock().unwrap().remove(key);
+    }
+}
+
+/// Token bucket, see `Limit`
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Full bucket
+    pub fn new(limit: &Limit) -> Bucket {
+        Bucket {
+            tokens: limit.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &Limit) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// Take a token, or tell how long until there is one
+    pub fn take(&mut self, limit: &Limit) -> Result<(), Duration> {
+        self.refill(limit);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate))
+        }
+    }
+}
+
+/// One bucket per key, like a user or a client address
+pub struct RateLimiter {
+    limit: Limit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Limit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token of the key, or tell how long until there is one
+    pub fn take(&self, key: &str) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_AT {
+            let limit = self.limit;
+            buckets.retain(|_, bucket| {
+                bucket.refill(&limit);
+                bucket.tokens < limit.burst
+            });
+        }
+        buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| Bucket::new(&self.limit))
+            .take(&self.limit)
+    }
+}
+
+/// Rate limiters shared by every worker, the ones of a single session are
+/// kept by the session
+pub struct RateLimits {
+    pub user_messages: RateLimiter,
+    pub room_messages: RateLimiter,
+    pub login_ip: RateLimiter,
+    pub login_user: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(limits: &Limits) -> RateLimits {
+        RateLimits {
+            user_messages: RateLimiter::new(limits.user_messages),
+            room_messages: RateLimiter::new(limits.room_messages),
+            login_ip: RateLimiter::new(limits.login_ip),
+            login_user: RateLimiter::new(limits.login_user),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Refills too slowly to matter while a test runs
+    const SLOW: Limit = Limit { rate: 0.001, burst: 2.0 };
+
+    #[test]
+    fn bucket_allows_its_burst() {
+        let mut bucket = Bucket::new(&SLOW);
+        assert!(bucket.take(&SLOW).is_ok());
+        assert!(bucket.take(&SLOW).is_ok());
+        let wait = bucket.take(&SLOW).unwrap_err();
+        assert!(wait > Duration::from_secs(900) && wait <= Duration::from_secs(1000));
+    }
+
+    #[test]
+    fn bucket_refills() {
+        let limit = Limit { rate: 1000.0, burst: 1.0 };
+        let mut bucket = Bucket::new(&limit);
+        assert!(bucket.take(&limit).is_ok());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(bucket.take(&limit).is_ok());
+    }
+
+    #[test]
+    fn rate_limiter_keeps_keys_apart() {
+        let limiter = RateLimiter::new(SLOW);
+        assert!(limiter.take("a").is_ok());
+        assert!(limiter.take("a").is_ok());
+        assert!(limiter.take("a").is_err());
+        assert!(limiter.take("b").is_ok());
+    }
+
+    #[test]
+    fn throttle_locks_after_failures() {
+        let throttle = Throttle::default();
+        for _ in 1..MAX_FAILURES {
+            throttle.fail("key");
+        }
+        assert_eq!(throttle.locked("key"), None);
+        throttle.fail("key");
+        assert!(throttle.locked("key").is_some_and(|left| left <= LOCKOUT));
+        assert_eq!(throttle.locked("other"), None);
+        throttle.reset("key");
+        assert_eq!(throttle.locked("key"), None);
+    }
+}
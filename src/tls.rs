@@ -0,0 +1,141 @@
+//! HTTPS listener. Certificates are handed out by a resolver that swaps in
+//! the files again whenever they change on disk, so renewals don't need a
+//! restart.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{dev::ServiceRequest, rt, web};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+
+fn invalid<E: std::fmt::Display>(path: &Path, err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {err}", path.display()))
+}
+
+/// Read the certificate chain and the first private key of PEM files
+fn load_certified_key(cert: &Path, key: &Path) -> io::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))?;
+    if certs.is_empty() {
+        return Err(invalid(cert, "no certificate found"));
+    }
+    let certs = certs.into_iter().map(Certificate).collect();
+
+    let der = rustls_pemfile::read_all(&mut BufReader::new(File::open(key)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(der),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(key, "no private key found"))?;
+    let signing_key = sign::any_supported_type(&PrivateKey(der)).map_err(|err| invalid(key, err))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Latest modification time of the files, `None` if one can't be read
+fn modified(paths: &[&Path]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+        .try_fold(SystemTime::UNIX_EPOCH, |latest, time| Some(latest.max(time?)))
+}
+
+/// Serves the certificate loaded last
+pub struct CertResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+    /// modification time of the files `current` was loaded from
+    loaded: Mutex<Option<SystemTime>>,
+}
+
+impl CertResolver {
+    pub fn load(cert: &Path, key: &Path) -> io::Result<CertResolver> {
+        let loaded = modified(&[cert, key]);
+        let current = load_certified_key(cert, key)?;
+        Ok(CertResolver {
+            cert: cert.to_owned(),
+            key: key.to_owned(),
+            current: RwLock::new(Arc::new(current)),
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    /// Load the files again if they changed. A broken certificate is
+    /// logged and the previous one kept, it is retried on the next call.
+    pub fn reload_if_changed(&self) {
+        let modified = modified(&[&self.cert, &self.key]);
+        let mut loaded = self.loaded.lock().unwrap();
+        if modified.is_none() || modified == *loaded {
+            return;
+        }
+        match load_certified_key(&self.cert, &self.key) {
+            Ok(current) => {
+                *self.current.write().unwrap() = Arc::new(current);
+                *loaded = modified;
+                log::info!("reloaded TLS certificate {}", self.cert.display());
+            }
+            Err(err) => log::error!("failed to reload TLS certificate: {err}"),
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+/// Watch the certificate files in the background
+pub fn spawn_reload(resolver: Arc<CertResolver>, interval: Duration) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let resolver = resolver.clone();
+            if let Err(err) = web::block(move || resolver.reload_if_changed()).await {
+                log::error!("failed to reload TLS certificate: {err}");
+            }
+        }
+    });
+}
+
+/// Where to redirect a plain HTTP request to, `None` for requests that came
+/// in over TLS or when redirecting is off
+pub fn redirect_location(req: &ServiceRequest, https_port: Option<u16>) -> Option<String> {
+    let port = https_port?;
+    if req.app_config().secure() {
+        return None;
+    }
+    let info = req.connection_info();
+    let host = info.host();
+    // strip the port, but not the end of an IPv6 address
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !host.ends_with(']') && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    Some(if port == 443 {
+        format!("https://{host}{path}")
+    } else {
+        format!("https://{host}:{port}{path}")
+    })
+}
@@ -0,0 +1,383 @@
+//! Optional TOTP second factor (RFC 6238, SHA1, 6 digits, 30 seconds) with
+//! single use recovery codes. Users with it enabled enter a code after
+//! their password before they are logged in. The HTTP endpoints are in
+//! `api`.
+
+use derive_more::Display;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{distributions::Alphanumeric, Rng};
+use sha1::Sha1;
+use sha256::digest;
+
+use crate::config::Config;
+use crate::models::{self, AuditEntry, RecoveryCode, Totp, User, UserState};
+use crate::store::{ChatStore, QueryError};
+
+/// Seconds a code is valid for
+const STEP_SECONDS: i64 = 30;
+
+/// Digits of a code
+const DIGITS: usize = 6;
+
+/// Bytes of a shared secret, 32 base32 characters
+const SECRET_BYTES: usize = 20;
+
+/// Recovery codes handed out at once
+const RECOVERY_CODES: usize = 10;
+
+/// Characters of a recovery code, shown in two halves
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Error of a two factor request
+#[derive(Debug, Display)]
+pub enum TwoFactorError {
+    #[display(fmt = "log in first")]
+    Unauthorized,
+    #[display(fmt = "only admins may reset or require two factor authentication")]
+    Forbidden,
+    #[display(fmt = "no such user")]
+    NotFound,
+    #[display(fmt = "two factor authentication is not set up")]
+    NotEnrolled,
+    #[display(fmt = "two factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[display(fmt = "wrong code")]
+    InvalidCode,
+    #[display(fmt = "your role requires two factor authentication")]
+    Required,
+    #[display(fmt = "{_0}")]
+    Query(QueryError),
+}
+
+impl std::error::Error for TwoFactorError {}
+
+impl From<QueryError> for TwoFactorError {
+    fn from(err: QueryError) -> Self {
+        TwoFactorError::Query(err)
+    }
+}
+
+impl From<actix_web::error::BlockingError> for TwoFactorError {
+    fn from(err: actix_web::error::BlockingError) -> Self {
+        TwoFactorError::Query(err.into())
+    }
+}
+
+impl TwoFactorError {
+    /// Short machine readable name of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            TwoFactorError::Unauthorized => "unauthorized",
+            TwoFactorError::Forbidden => "forbidden",
+            TwoFactorError::NotFound => "not_found",
+            TwoFactorError::NotEnrolled => "not_enrolled",
+            TwoFactorError::AlreadyEnabled => "already_enabled",
+            TwoFactorError::InvalidCode => "invalid_code",
+            TwoFactorError::Required => "two_factor_required",
+            TwoFactorError::Query(err) => err.code(),
+        }
+    }
+}
+
+impl actix_web::ResponseError for TwoFactorError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            TwoFactorError::Unauthorized => StatusCode::UNAUTHORIZED,
+            TwoFactorError::Forbidden | TwoFactorError::Required => StatusCode::FORBIDDEN,
+            TwoFactorError::NotFound => StatusCode::NOT_FOUND,
+            TwoFactorError::NotEnrolled | TwoFactorError::AlreadyEnabled => StatusCode::CONFLICT,
+            TwoFactorError::InvalidCode => StatusCode::BAD_REQUEST,
+            TwoFactorError::Query(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        actix_web::HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "type": "error",
+            "code": self.code(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+/// RFC 4648 base32 without padding
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(BASE32[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        text.push(BASE32[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    text
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE32.iter().position(|&b| b == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// The code of a time step
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+    let value = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    (value & 0x7fff_ffff) % 10u32.pow(DIGITS as u32)
+}
+
+/// Time step `code` belongs to. The steps before and after the current one
+/// count as well, clocks drift.
+fn matching_step(secret: &str, code: &str) -> Option<i64> {
+    let secret = base32_decode(secret)?;
+    let code: u32 = code.parse().ok()?;
+    let now = chrono::Utc::now().timestamp() / STEP_SECONDS;
+    (now - 1..=now + 1).find(|&step| code_at(&secret, step) == code)
+}
+
+/// Codes are entered with spaces and dashes at times
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Check a TOTP or recovery code of a user with two factor authentication
+/// enabled, using it up
+fn accept(store: &dyn ChatStore, totp: &Totp, code: &str) -> Result<bool, QueryError> {
+    let code = normalize(code);
+    if code.len() == DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        return match matching_step(&totp.secret, &code) {
+            // a code seen before was overheard or is being replayed
+            Some(step) => Ok(store.update_totp_step(&totp.user_id, step)? == 1),
+            None => Ok(false),
+        };
+    }
+    Ok(store.delete_recovery_code(&totp.user_id, &digest(code.as_str()))? == 1)
+}
+
+/// Hand out a new set of recovery codes, the earlier ones stop working
+fn new_recovery_codes(store: &dyn ChatStore, user_id: &str) -> Result<Vec<String>, QueryError> {
+    let mut rng = rand::thread_rng();
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect()
+        })
+        .collect();
+    let rows: Vec<RecoveryCode> = codes
+        .iter()
+        .map(|code| RecoveryCode {
+            user_id: user_id.to_owned(),
+            code: digest(code.as_str()),
+        })
+        .collect();
+    store.replace_recovery_codes(&rows)?;
+    let half = RECOVERY_CODE_LENGTH / 2;
+    Ok(codes.iter().map(|code| format!("{}-{}", &code[..half], &code[half..])).collect())
+}
+
+fn enabled_totp(store: &dyn ChatStore, user_id: &str) -> Result<Option<Totp>, QueryError> {
+    Ok(store.query_totp(user_id)?.filter(|totp| totp.enabled_at.is_some()))
+}
+
+/// Whether the user has two factor authentication enabled
+pub fn enabled(store: &dyn ChatStore, user_id: &str) -> Result<bool, QueryError> {
+    Ok(enabled_totp(store, user_id)?.is_some())
+}
+
+/// Whether the user's role requires two factor authentication: admins and
+/// room moderators or owners, as far as the configuration asks for it, and
+/// users an admin required it of
+pub fn required(store: &dyn ChatStore, config: &Config, user: &User) -> Result<bool, QueryError> {
+    if user.require_2fa || (config.require_2fa_admins && user.permission_id == 1) {
+        return Ok(true);
+    }
+    if config.require_2fa_moderators {
+        let memberships = store.query_memberships(&user.uuid)?;
+        return Ok(memberships.iter().any(|m| m.role >= models::ROLE_MODERATOR));
+    }
+    Ok(false)
+}
+
+/// Whether the user's role requires two factor authentication they have
+/// not set up yet
+pub fn missing(store: &dyn ChatStore, config: &Config, user: &User) -> Result<bool, QueryError> {
+    Ok(required(store, config, user)? && !enabled(store, &user.uuid)?)
+}
+
+/// Start enrolling: a new secret, unused until a code is confirmed.
+/// Returns the secret and the `otpauth://` provisioning URI for it.
+pub fn enroll(store: &dyn ChatStore, config: &Config, user: &User) -> Result<(String, String), TwoFactorError> {
+    if enabled(store, &user.uuid)? {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+    let secret = base32_encode(&rand::thread_rng().gen::<[u8; SECRET_BYTES]>());
+    store.update_totp(&Totp::from_details(&user.uuid, &secret))?;
+    let issuer = utf8_percent_encode(&config.totp_issuer, NON_ALPHANUMERIC).to_string();
+    let account = utf8_percent_encode(&user.name, NON_ALPHANUMERIC);
+    let uri = format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    );
+    Ok((secret, uri))
+}
+
+/// Finish enrolling with a code of the new secret. Returns the recovery
+/// codes, they are only shown this once.
+pub fn confirm(store: &dyn ChatStore, user: &User, code: &str) -> Result<Vec<String>, TwoFactorError> {
+    let mut totp = store.query_totp(&user.uuid)?.ok_or(TwoFactorError::NotEnrolled)?;
+    if totp.enabled_at.is_some() {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+    let step = matching_step(&totp.secret, &normalize(code)).ok_or(TwoFactorError::InvalidCode)?;
+    totp.enabled_at = Some(chrono::Utc::now().naive_utc());
+    totp.last_step = step;
+    store.update_totp(&totp)?;
+    Ok(new_recovery_codes(store, &user.uuid)?)
+}
+
+/// Replace the recovery codes, `code` is a current TOTP or recovery code
+pub fn regenerate(store: &dyn ChatStore, user: &User, code: &str) -> Result<Vec<String>, TwoFactorError> {
+    let totp = enabled_totp(store, &user.uuid)?.ok_or(TwoFactorError::NotEnrolled)?;
+    if !accept(store, &totp, code)? {
+        return Err(TwoFactorError::InvalidCode);
+    }
+    Ok(new_recovery_codes(store, &user.uuid)?)
+}
+
+/// Turn two factor authentication off, unless the user's role requires it
+pub fn disable(store: &dyn ChatStore, config: &Config, user: &User, code: &str) -> Result<(), TwoFactorError> {
+    let totp = enabled_totp(store, &user.uuid)?.ok_or(TwoFactorError::NotEnrolled)?;
+    if required(store, config, user)? {
+        return Err(TwoFactorError::Required);
+    }
+    if !accept(store, &totp, code)? {
+        return Err(TwoFactorError::InvalidCode);
+    }
+    store.delete_totp(&user.uuid)?;
+    Ok(())
+}
+
+/// Turn two factor authentication off for a user who lost their device and
+/// recovery codes, admins only
+pub fn reset(store: &dyn ChatStore, actor: &User, user_id: &str) -> Result<(), TwoFactorError> {
+    if actor.permission_id != 1 {
+        return Err(TwoFactorError::Forbidden);
+    }
+    store
+        .query_user_from_id(user_id)?
+        .filter(|user| UserState::from_i32(user.state) != UserState::Deleted)
+        .ok_or(TwoFactorError::NotFound)?;
+    if store.delete_totp(user_id)? == 0 {
+        return Err(TwoFactorError::NotEnrolled);
+    }
+    let entry = AuditEntry::from_details(&actor.uuid, "reset_2fa", Some(user_id.to_owned()), None, None);
+    store.insert_audit(&entry)?;
+    Ok(())
+}
+
+/// Require two factor authentication of a user whatever their role, or
+/// leave it to the configuration again, admins only
+pub fn set_required(store: &dyn ChatStore, actor: &User, user_id: &str, required: bool) -> Result<(), TwoFactorError> {
+    if actor.permission_id != 1 {
+        return Err(TwoFactorError::Forbidden);
+    }
+    store
+        .query_user_from_id(user_id)?
+        .filter(|user| UserState::from_i32(user.state) != UserState::Deleted)
+        .ok_or(TwoFactorError::NotFound)?;
+    store.update_user_require_2fa(user_id, required)?;
+    let action = if required { "require_2fa" } else { "waive_2fa" };
+    let entry = AuditEntry::from_details(&actor.uuid, action, Some(user_id.to_owned()), None, None);
+    store.insert_audit(&entry)?;
+    Ok(())
+}
+
+/// Second step of a login: the user if `code` is one of theirs
+pub fn verify_login(store: &dyn ChatStore, user_id: &str, code: &str) -> Result<Option<User>, QueryError> {
+    let Some(totp) = enabled_totp(store, user_id)? else {
+        return Ok(None);
+    };
+    if !accept(store, &totp, code)? {
+        return Ok(None);
+    }
+    store.query_user_from_id(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    /// Secret of the SHA1 test vectors of RFC 6238 Appendix B
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc_6238_vectors() {
+        // the RFC lists 8 digits, the last 6 are ours
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(code_at(RFC_SECRET, time / STEP_SECONDS), code % 1_000_000, "at {time}");
+        }
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        // RFC 4648 test vectors, without padding
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").as_deref(), Some(&b"foobar"[..]));
+        for len in 0..=SECRET_BYTES {
+            let bytes: Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(37)).collect();
+            assert_eq!(base32_decode(&base32_encode(&bytes)), Some(bytes));
+        }
+        assert_eq!(base32_decode("not base32!"), None);
+    }
+
+    #[test]
+    fn codes_are_not_replayed() {
+        let store = MemoryStore::default();
+        let mut totp = Totp::from_details("user", base32_encode(RFC_SECRET));
+        totp.enabled_at = Some(chrono::Utc::now().naive_utc());
+        store.update_totp(&totp).unwrap();
+
+        let now = chrono::Utc::now().timestamp() / STEP_SECONDS;
+        let code = |step| format!("{:06}", code_at(RFC_SECRET, step));
+        assert!(accept(&store, &totp, &code(now)).unwrap());
+        assert!(!accept(&store, &totp, &code(now)).unwrap());
+        // an earlier step of the window is over once a later one was used
+        assert!(!accept(&store, &totp, &code(now - 1)).unwrap());
+        assert!(accept(&store, &totp, &code(now + 1)).unwrap());
+    }
+}